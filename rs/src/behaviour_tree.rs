@@ -0,0 +1,844 @@
+use crate::events::Event;
+use crate::motion::MotionHandle;
+use crate::registry::Registry;
+use crate::robo::{Robot, TaskFunc};
+use config::Config;
+use ev3dev_lang_rust::{Ev3Button, Ev3Error, Ev3Result};
+use serde_derive::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const TREES_DIR: &str = "/home/robot/SDP2022/rs/trees";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    Success,
+    Failure,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineSensorSelect {
+    Left,
+    Middle,
+    Right,
+    /// at least one of the three
+    Any,
+}
+
+/// A node as written in a tree file, see `trees/push_block.yaml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeDef {
+    /// run the children one after another, fails with the first failing child
+    Sequence { children: Vec<NodeDef> },
+    /// run the children one after another until one succeeds
+    Selector { children: Vec<NodeDef> },
+    /// tick all children every tick, succeeds once `success` children succeeded
+    /// (default: all), fails once that is not possible anymore
+    Parallel {
+        children: Vec<NodeDef>,
+        success: Option<usize>,
+    },
+    Invert { child: Box<NodeDef> },
+    /// running until the child succeeds
+    UntilSuccess { child: Box<NodeDef> },
+    /// run the child `count` times (default: forever), fails when the child fails
+    Repeat {
+        child: Box<NodeDef>,
+        count: Option<u32>,
+    },
+    /// run the child again after a failure, at most `attempts` times
+    Retry { child: Box<NodeDef>, attempts: u32 },
+    /// stop the child after `ms`, then fail (or succeed with `succeed: true`)
+    Timeout {
+        child: Box<NodeDef>,
+        ms: u64,
+        #[serde(default)]
+        succeed: bool,
+    },
+
+    /// `follow_line_once` (the budget is checked by `BehaviourTree::run`), always running
+    FollowLine,
+    /// an action from the registry (`--list-tasks`), blocks until done
+    Action { name: String },
+    /// a condition from the registry (`--list-tasks`)
+    Condition { name: String },
+    /// `MoveSteering::on`
+    Steering { steering: i32, speed: i32 },
+    Stop,
+    OnForRotations {
+        steering: i32,
+        speed: i32,
+        rotations: f32,
+    },
+    OnForSeconds {
+        steering: i32,
+        speed: i32,
+        seconds: f32,
+    },
+    /// `Robot::turn`, blocks until done
+    Turn {
+        steering: i32,
+        speed: i32,
+        rotations: f32,
+        angle: Option<f32>,
+    },
+    DriveCm { distance: f32, speed: i32 },
+    TurnDegrees { angle: f32, speed: i32 },
+    /// ultrasonic distance in cm
+    Distance {
+        below: Option<f32>,
+        above: Option<f32>,
+    },
+    OnLine {
+        sensor: LineSensorSelect,
+        #[serde(default = "default_true")]
+        on: bool,
+    },
+    Wait { ms: u64 },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// What the nodes tick on: the robot, or a stub in the tests.
+pub trait Context {
+    fn publish(&mut self, event: Event);
+}
+
+impl Context for Robot {
+    fn publish(&mut self, event: Event) {
+        self.events.publish(event);
+    }
+}
+
+/// a node without children
+pub trait LeafNode<T> {
+    fn tick(&mut self, ctx: &mut T) -> Ev3Result<Status>;
+    /// stop what the leaf started and reset it
+    fn halt(&mut self) -> Ev3Result<()>;
+}
+
+pub enum Node<T = Robot> {
+    Sequence {
+        children: Vec<Node<T>>,
+        current: usize,
+    },
+    Selector {
+        children: Vec<Node<T>>,
+        current: usize,
+    },
+    Parallel {
+        children: Vec<Node<T>>,
+        success: usize,
+        results: Vec<Option<Status>>,
+    },
+    Invert(Box<Node<T>>),
+    UntilSuccess(Box<Node<T>>),
+    Repeat {
+        child: Box<Node<T>>,
+        count: Option<u32>,
+        done: u32,
+    },
+    Retry {
+        child: Box<Node<T>>,
+        attempts: u32,
+        failed: u32,
+    },
+    Timeout {
+        child: Box<Node<T>>,
+        timeout: Duration,
+        succeed: bool,
+        started: Option<Instant>,
+    },
+    Leaf(Box<dyn LeafNode<T>>),
+}
+
+pub enum LeafFunc {
+    Action(TaskFunc<()>),
+    Condition(TaskFunc<bool>),
+}
+
+/// the leaves of `NodeDef` on the robot
+pub struct RobotLeaf {
+    def: NodeDef,
+    func: Option<LeafFunc>,
+    handle: Option<MotionHandle>,
+    started: Option<Instant>,
+}
+
+fn unknown(kind: &str, name: &str) -> Ev3Error {
+    Ev3Error::InternalError {
+        msg: format!("unknown {} `{}`, see --list-tasks", kind, name),
+    }
+}
+
+impl Node {
+    pub fn build(def: &NodeDef, registry: &Registry) -> Ev3Result<Self> {
+        let build_all = |children: &Vec<NodeDef>| {
+            children
+                .iter()
+                .map(|child| Node::build(child, registry))
+                .collect::<Ev3Result<Vec<_>>>()
+        };
+        let build_box = |child: &NodeDef| Node::build(child, registry).map(Box::new);
+        Ok(match def {
+            NodeDef::Sequence { children } => Node::Sequence {
+                children: build_all(children)?,
+                current: 0,
+            },
+            NodeDef::Selector { children } => Node::Selector {
+                children: build_all(children)?,
+                current: 0,
+            },
+            NodeDef::Parallel { children, success } => Node::Parallel {
+                success: success.unwrap_or(children.len()),
+                results: vec![None; children.len()],
+                children: build_all(children)?,
+            },
+            NodeDef::Invert { child } => Node::Invert(build_box(child)?),
+            NodeDef::UntilSuccess { child } => Node::UntilSuccess(build_box(child)?),
+            NodeDef::Repeat { child, count } => Node::Repeat {
+                child: build_box(child)?,
+                count: *count,
+                done: 0,
+            },
+            NodeDef::Retry { child, attempts } => Node::Retry {
+                child: build_box(child)?,
+                attempts: *attempts,
+                failed: 0,
+            },
+            NodeDef::Timeout { child, ms, succeed } => Node::Timeout {
+                child: build_box(child)?,
+                timeout: Duration::from_millis(*ms),
+                succeed: *succeed,
+                started: None,
+            },
+            leaf => Node::Leaf(Box::new(RobotLeaf {
+                func: match leaf {
+                    NodeDef::Action { name } => Some(LeafFunc::Action(
+                        registry
+                            .get_action(name)
+                            .ok_or_else(|| unknown("action", name))?,
+                    )),
+                    NodeDef::Condition { name } => Some(LeafFunc::Condition(
+                        registry
+                            .get_cond(name)
+                            .ok_or_else(|| unknown("condition", name))?,
+                    )),
+                    _ => None,
+                },
+                def: leaf.clone(),
+                handle: None,
+                started: None,
+            })),
+        })
+    }
+}
+
+impl<T: Context> Node<T> {
+    /// stop a running node and reset it so the next tick starts it again
+    pub fn halt(&mut self) -> Ev3Result<()> {
+        match self {
+            Node::Sequence { children, current } | Node::Selector { children, current } => {
+                for child in children.iter_mut() {
+                    child.halt()?;
+                }
+                *current = 0;
+            }
+            Node::Parallel {
+                children, results, ..
+            } => {
+                for child in children.iter_mut() {
+                    child.halt()?;
+                }
+                results.iter_mut().for_each(|result| *result = None);
+            }
+            Node::Invert(child) | Node::UntilSuccess(child) => child.halt()?,
+            Node::Repeat { child, done, .. } => {
+                child.halt()?;
+                *done = 0;
+            }
+            Node::Retry { child, failed, .. } => {
+                child.halt()?;
+                *failed = 0;
+            }
+            Node::Timeout { child, started, .. } => {
+                child.halt()?;
+                *started = None;
+            }
+            Node::Leaf(leaf) => leaf.halt()?,
+        }
+        Ok(())
+    }
+
+    pub fn tick(&mut self, ctx: &mut T) -> Ev3Result<Status> {
+        match self {
+            Node::Sequence { children, current } => {
+                while *current < children.len() {
+                    match children[*current].tick(ctx)? {
+                        Status::Success => *current += 1,
+                        Status::Running => return Ok(Status::Running),
+                        Status::Failure => {
+                            *current = 0;
+                            return Ok(Status::Failure);
+                        }
+                    }
+                }
+                *current = 0;
+                Ok(Status::Success)
+            }
+            Node::Selector { children, current } => {
+                while *current < children.len() {
+                    match children[*current].tick(ctx)? {
+                        Status::Failure => *current += 1,
+                        Status::Running => return Ok(Status::Running),
+                        Status::Success => {
+                            *current = 0;
+                            return Ok(Status::Success);
+                        }
+                    }
+                }
+                *current = 0;
+                Ok(Status::Failure)
+            }
+            Node::Parallel {
+                children,
+                success,
+                results,
+            } => {
+                for (child, result) in children.iter_mut().zip(results.iter_mut()) {
+                    if result.is_none() {
+                        match child.tick(ctx)? {
+                            Status::Running => {}
+                            status => *result = Some(status),
+                        }
+                    }
+                }
+                let succeeded = results
+                    .iter()
+                    .filter(|result| **result == Some(Status::Success))
+                    .count();
+                let failed = results
+                    .iter()
+                    .filter(|result| **result == Some(Status::Failure))
+                    .count();
+                let status = if succeeded >= *success {
+                    Status::Success
+                } else if children.len() - failed < *success {
+                    Status::Failure
+                } else {
+                    return Ok(Status::Running);
+                };
+                for child in children.iter_mut() {
+                    child.halt()?;
+                }
+                results.iter_mut().for_each(|result| *result = None);
+                Ok(status)
+            }
+            Node::Invert(child) => Ok(match child.tick(ctx)? {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            }),
+            Node::UntilSuccess(child) => Ok(match child.tick(ctx)? {
+                Status::Success => Status::Success,
+                _ => Status::Running,
+            }),
+            Node::Repeat { child, count, done } => match child.tick(ctx)? {
+                Status::Success => {
+                    *done += 1;
+                    if count.is_some_and(|count| *done >= count) {
+                        *done = 0;
+                        Ok(Status::Success)
+                    } else {
+                        Ok(Status::Running)
+                    }
+                }
+                Status::Failure => {
+                    *done = 0;
+                    Ok(Status::Failure)
+                }
+                Status::Running => Ok(Status::Running),
+            },
+            Node::Retry {
+                child,
+                attempts,
+                failed,
+            } => match child.tick(ctx)? {
+                Status::Failure => {
+                    *failed += 1;
+                    if *failed >= *attempts {
+                        *failed = 0;
+                        Ok(Status::Failure)
+                    } else {
+                        ctx.publish(Event::TreeRetry {
+                            attempt: *failed + 1,
+                            attempts: *attempts,
+                        });
+                        Ok(Status::Running)
+                    }
+                }
+                Status::Success => {
+                    *failed = 0;
+                    Ok(Status::Success)
+                }
+                Status::Running => Ok(Status::Running),
+            },
+            Node::Timeout {
+                child,
+                timeout,
+                succeed,
+                started,
+            } => {
+                let since = *started.get_or_insert_with(Instant::now);
+                if since.elapsed() >= *timeout {
+                    child.halt()?;
+                    *started = None;
+                    return Ok(if *succeed {
+                        Status::Success
+                    } else {
+                        Status::Failure
+                    });
+                }
+                let status = child.tick(ctx)?;
+                if status != Status::Running {
+                    *started = None;
+                }
+                Ok(status)
+            }
+            Node::Leaf(leaf) => leaf.tick(ctx),
+        }
+    }
+}
+
+fn status(success: bool) -> Status {
+    if success {
+        Status::Success
+    } else {
+        Status::Failure
+    }
+}
+
+impl LeafNode<Robot> for RobotLeaf {
+    fn halt(&mut self) -> Ev3Result<()> {
+        if let Some(handle) = self.handle.take() {
+            handle.cancel()?;
+        }
+        self.started = None;
+        Ok(())
+    }
+
+    fn tick(&mut self, robo: &mut Robot) -> Ev3Result<Status> {
+        let RobotLeaf {
+            def,
+            func,
+            handle,
+            started,
+        } = self;
+        // moves that end on their own keep running over several ticks
+        if let Some(running) = handle {
+            if running.is_done()? {
+                *handle = None;
+                return Ok(Status::Success);
+            }
+            return Ok(Status::Running);
+        }
+        Ok(match def {
+            NodeDef::FollowLine => {
+                // `BehaviourTree::run` checks the budget and the monitors once per tick
+                robo.follow_line_step()?;
+                Status::Running
+            }
+            NodeDef::Action { .. } | NodeDef::Condition { .. } => match func {
+                Some(LeafFunc::Action(act)) => {
+                    act(robo)?;
+                    Status::Success
+                }
+                Some(LeafFunc::Condition(cond)) => status(cond(robo)?),
+                None => Status::Failure,
+            },
+            NodeDef::Steering { steering, speed } => {
+                robo.steering.on(*steering, *speed)?;
+                Status::Success
+            }
+            NodeDef::Stop => {
+                robo.steering.off()?;
+                Status::Success
+            }
+            NodeDef::OnForRotations {
+                steering,
+                speed,
+                rotations,
+            } => {
+                *handle = Some(
+                    robo.steering
+                        .on_for_rotations(*steering, *speed, *rotations, false)?,
+                );
+                Status::Running
+            }
+            NodeDef::OnForSeconds {
+                steering,
+                speed,
+                seconds,
+            } => {
+                *handle = Some(
+                    robo.steering
+                        .on_for_seconds(*steering, *speed, *seconds, false)?,
+                );
+                Status::Running
+            }
+            NodeDef::Turn {
+                steering,
+                speed,
+                rotations,
+                angle,
+            } => {
+                robo.turn(*steering, *speed, *rotations, *angle)?;
+                Status::Success
+            }
+            NodeDef::DriveCm { distance, speed } => {
+                *handle = Some(robo.steering.drive_cm(*distance, *speed, None, false)?);
+                Status::Running
+            }
+            NodeDef::TurnDegrees { angle, speed } => {
+                *handle = Some(robo.steering.turn_degrees(*angle, *speed, None, false)?);
+                Status::Running
+            }
+            NodeDef::Distance { below, above } => {
+                let distance = robo.us_sensor.get_distance_centimeters()?;
+                status(
+                    below.is_none_or(|below| distance < below)
+                        && above.is_none_or(|above| distance > above),
+                )
+            }
+            NodeDef::OnLine { sensor, on } => {
+                let on_line = match sensor {
+                    LineSensorSelect::Left => robo.left_sensor.on_line(),
+                    LineSensorSelect::Middle => robo.middle_sensor.on_line(),
+                    LineSensorSelect::Right => robo.right_sensor.on_line(),
+                    LineSensorSelect::Any => {
+                        robo.left_sensor.on_line()
+                            || robo.middle_sensor.on_line()
+                            || robo.right_sensor.on_line()
+                    }
+                };
+                status(on_line == *on)
+            }
+            NodeDef::Wait { ms } => {
+                let since = *started.get_or_insert_with(Instant::now);
+                if since.elapsed() >= Duration::from_millis(*ms) {
+                    *started = None;
+                    Status::Success
+                } else {
+                    Status::Running
+                }
+            }
+            _ => Status::Failure,
+        })
+    }
+}
+
+pub struct BehaviourTree {
+    pub name: String,
+    pub root: Node,
+}
+
+impl BehaviourTree {
+    pub fn new(name: String, def: &NodeDef, registry: &Registry) -> Ev3Result<Self> {
+        Ok(Self {
+            name,
+            root: Node::build(def, registry)?,
+        })
+    }
+
+    /// the file contains the root node
+    pub fn load(file: &std::path::Path, registry: &Registry) -> Ev3Result<Self> {
+        Self::new(file.display().to_string(), &load_def(file)?, registry)
+    }
+
+    pub fn tick(&mut self, robo: &mut Robot) -> Ev3Result<Status> {
+        self.root.tick(robo)
+    }
+
+    /// tick until the tree is done or a button is pressed
+    pub fn run(&mut self, robo: &mut Robot) -> Ev3Result<Status> {
+        let button = Ev3Button::new()?;
+        robo.events.publish(Event::TreeStarted {
+            name: self.name.clone(),
+        });
+        loop {
+            button.process();
+            if !button.get_pressed_buttons().is_empty() {
+                self.root.halt()?;
+                robo.steering.off()?;
+                return Ok(Status::Failure);
+            }
+            robo.update_odometry()?;
+            if let Err(e) = robo.check_budget() {
+                self.root.halt()?;
+                robo.steering.off()?;
+                return Err(e);
+            }
+            let status = self.tick(robo)?;
+            if status != Status::Running {
+                robo.events.publish(Event::TreeFinished {
+                    name: self.name.clone(),
+                    status,
+                });
+                return Ok(status);
+            }
+        }
+    }
+}
+
+fn load_def(file: &std::path::Path) -> Ev3Result<NodeDef> {
+    Config::builder()
+        .add_source(config::File::with_name(file.to_str().unwrap_or("")))
+        .build()
+        .and_then(|config| config.try_deserialize())
+        .map_err(|e| Ev3Error::InternalError {
+            msg: format!("invalid tree {}: {}", file.display(), e),
+        })
+}
+
+/// add the tree in `file` as the action `tree_<file name>`, it is built from the actions
+/// and conditions registered so far and fails when the tree fails
+pub fn register(registry: &mut Registry, file: &std::path::Path) -> Ev3Result<()> {
+    let def = load_def(file)?;
+    let name = file.display().to_string();
+    // report unknown actions and conditions now instead of in the middle of the course
+    BehaviourTree::new(name.clone(), &def, registry)?;
+    let known = Arc::new(registry.clone());
+    let stem = file
+        .file_stem()
+        .map_or_else(|| name.clone(), |stem| stem.to_string_lossy().to_string());
+    registry.add_action(
+        format!("tree_{}", stem),
+        format!("behaviour tree {}", name),
+        Arc::new(move |robo| {
+            match BehaviourTree::new(name.clone(), &def, &known)?.run(robo)? {
+                Status::Success => Ok(()),
+                status => Err(Ev3Error::InternalError {
+                    msg: format!("tree {} finished with {:?}", name, status),
+                }),
+            }
+        }),
+    )
+}
+
+/// register every `.yaml` file in `TREES_DIR`, then `files`, trees that fail to load are skipped
+pub fn register_all(registry: &mut Registry, files: &[std::path::PathBuf]) {
+    let mut trees = std::fs::read_dir(TREES_DIR)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "yaml"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    trees.sort();
+    let canonical = |file: &std::path::PathBuf| std::fs::canonicalize(file).ok();
+    let loaded: Vec<_> = trees.iter().map(canonical).collect();
+    // e.g. `--tree trees/push_block.yaml` is already there
+    let files = files
+        .iter()
+        .filter(|file| canonical(file).is_none_or(|file| !loaded.contains(&Some(file))));
+    for file in trees.iter().chain(files) {
+        if let Err(e) = register(registry, file) {
+            println!("skipping tree {}: {:?}", file.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Status::{Failure, Running, Success};
+
+    /// which leaves were ticked and what was published
+    #[derive(Default)]
+    struct Log {
+        ticks: Vec<&'static str>,
+        events: Vec<Event>,
+    }
+
+    impl Context for Log {
+        fn publish(&mut self, event: Event) {
+            self.events.push(event);
+        }
+    }
+
+    /// returns `statuses` one per tick, then the last one, `halt` starts over
+    struct Stub {
+        name: &'static str,
+        statuses: Vec<Status>,
+        next: usize,
+    }
+
+    impl LeafNode<Log> for Stub {
+        fn tick(&mut self, log: &mut Log) -> Ev3Result<Status> {
+            log.ticks.push(self.name);
+            let status = self.statuses[self.next.min(self.statuses.len() - 1)];
+            self.next += 1;
+            Ok(status)
+        }
+
+        fn halt(&mut self) -> Ev3Result<()> {
+            self.next = 0;
+            Ok(())
+        }
+    }
+
+    fn stub(name: &'static str, statuses: &[Status]) -> Node<Log> {
+        Node::Leaf(Box::new(Stub {
+            name,
+            statuses: statuses.to_vec(),
+            next: 0,
+        }))
+    }
+
+    fn run(node: &mut Node<Log>, ticks: usize) -> (Vec<Status>, Log) {
+        let mut log = Log::default();
+        let statuses = (0..ticks).map(|_| node.tick(&mut log).unwrap()).collect();
+        (statuses, log)
+    }
+
+    #[test]
+    fn sequence() {
+        let mut node = Node::Sequence {
+            children: vec![stub("a", &[Running, Success]), stub("b", &[Success])],
+            current: 0,
+        };
+        let (statuses, log) = run(&mut node, 2);
+        assert_eq!(statuses, [Running, Success]);
+        // the running child is not ticked again from the start
+        assert_eq!(log.ticks, ["a", "a", "b"]);
+
+        let mut node = Node::Sequence {
+            children: vec![stub("a", &[Failure]), stub("b", &[Success])],
+            current: 0,
+        };
+        let (statuses, log) = run(&mut node, 1);
+        assert_eq!(statuses, [Failure]);
+        assert_eq!(log.ticks, ["a"]);
+    }
+
+    #[test]
+    fn selector() {
+        let mut node = Node::Selector {
+            children: vec![stub("a", &[Failure]), stub("b", &[Running, Success])],
+            current: 0,
+        };
+        let (statuses, log) = run(&mut node, 2);
+        assert_eq!(statuses, [Running, Success]);
+        assert_eq!(log.ticks, ["a", "b", "b"]);
+
+        let mut node = Node::Selector {
+            children: vec![stub("a", &[Failure]), stub("b", &[Failure])],
+            current: 0,
+        };
+        assert_eq!(run(&mut node, 1).0, [Failure]);
+    }
+
+    #[test]
+    fn parallel() {
+        let children = || vec![stub("a", &[Running, Success]), stub("b", &[Failure])];
+        let mut node = Node::Parallel {
+            results: vec![None; 2],
+            children: children(),
+            success: 1,
+        };
+        let (statuses, log) = run(&mut node, 2);
+        assert_eq!(statuses, [Running, Success]);
+        // finished children are not ticked again
+        assert_eq!(log.ticks, ["a", "b", "a"]);
+
+        let mut node = Node::Parallel {
+            results: vec![None; 2],
+            children: children(),
+            success: 2,
+        };
+        assert_eq!(run(&mut node, 1).0, [Failure]);
+    }
+
+    #[test]
+    fn invert() {
+        let mut node = Node::Invert(Box::new(stub("a", &[Running, Success, Failure])));
+        assert_eq!(run(&mut node, 3).0, [Running, Failure, Success]);
+    }
+
+    #[test]
+    fn repeat() {
+        let mut node = Node::Repeat {
+            child: Box::new(stub("a", &[Success])),
+            count: Some(3),
+            done: 0,
+        };
+        assert_eq!(run(&mut node, 4).0, [Running, Running, Success, Running]);
+
+        let mut node = Node::Repeat {
+            child: Box::new(stub("a", &[Success, Failure])),
+            count: None,
+            done: 0,
+        };
+        assert_eq!(run(&mut node, 2).0, [Running, Failure]);
+    }
+
+    #[test]
+    fn retry() {
+        let mut node = Node::Retry {
+            child: Box::new(stub("a", &[Failure, Failure, Success])),
+            attempts: 3,
+            failed: 0,
+        };
+        let (statuses, log) = run(&mut node, 3);
+        assert_eq!(statuses, [Running, Running, Success]);
+        assert_eq!(
+            log.events,
+            [
+                Event::TreeRetry {
+                    attempt: 2,
+                    attempts: 3
+                },
+                Event::TreeRetry {
+                    attempt: 3,
+                    attempts: 3
+                },
+            ]
+        );
+
+        let mut node = Node::Retry {
+            child: Box::new(stub("a", &[Failure])),
+            attempts: 2,
+            failed: 0,
+        };
+        assert_eq!(run(&mut node, 2).0, [Running, Failure]);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut node = Node::Timeout {
+            child: Box::new(stub("a", &[Running, Success])),
+            timeout: Duration::from_secs(60),
+            succeed: false,
+            started: None,
+        };
+        assert_eq!(run(&mut node, 2).0, [Running, Success]);
+
+        // already over, the child is halted instead of ticked
+        for (succeed, status) in [(false, Failure), (true, Success)] {
+            let mut node = Node::Timeout {
+                child: Box::new(stub("a", &[Running])),
+                timeout: Duration::ZERO,
+                succeed,
+                started: None,
+            };
+            let (statuses, log) = run(&mut node, 1);
+            assert_eq!(statuses, [status]);
+            assert!(log.ticks.is_empty());
+        }
+    }
+}
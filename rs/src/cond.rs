@@ -0,0 +1,207 @@
+//! Building blocks for `Task::cond`, e.g.
+//! `cond::and(Box::new(cond_dist), cond::after_delay(Duration::from_secs(3)))`,
+//! and `CondDef` to write them in mission files, e.g.
+//! `cond: {and: [dist, {after_distance: 50.0}]}`.
+//!
+//! Combinators always evaluate all their children, so stateful ones like
+//! `consecutive` see every tick.
+use crate::registry::Registry;
+use crate::robo::{Robot, TaskFunc};
+use ev3dev_lang_rust::{Ev3Error, Ev3Result};
+use serde_derive::Deserialize;
+use std::cell::Cell;
+use std::time::Duration;
+
+/// a condition on `T`, the same as `TaskFunc<bool>` for a `Robot`
+pub type CondFunc<T> = Box<dyn Fn(&mut T) -> Ev3Result<bool> + Send>;
+
+pub fn and<T: 'static>(a: CondFunc<T>, b: CondFunc<T>) -> CondFunc<T> {
+    Box::new(move |robo: &mut T| {
+        let a = a(robo)?;
+        let b = b(robo)?;
+        Ok(a && b)
+    })
+}
+
+pub fn or<T: 'static>(a: CondFunc<T>, b: CondFunc<T>) -> CondFunc<T> {
+    Box::new(move |robo: &mut T| {
+        let a = a(robo)?;
+        let b = b(robo)?;
+        Ok(a || b)
+    })
+}
+
+pub fn not<T: 'static>(cond: CondFunc<T>) -> CondFunc<T> {
+    Box::new(move |robo: &mut T| Ok(!cond(robo)?))
+}
+
+/// true once `cond` was true for `count` evaluations in a row
+pub fn consecutive<T: 'static>(count: u32, cond: CondFunc<T>) -> CondFunc<T> {
+    let seen = Cell::new(0);
+    Box::new(move |robo: &mut T| {
+        if cond(robo)? {
+            seen.set(seen.get() + 1);
+        } else {
+            seen.set(0);
+        }
+        Ok(seen.get() >= count)
+    })
+}
+
+/// true once `delay` passed since the line following for this task started
+pub fn after_delay(delay: Duration) -> TaskFunc<bool> {
+    Box::new(move |robo: &mut Robot| Ok(robo.follow_started.elapsed() >= delay))
+}
+
+/// true once the robot drove `distance` cm since the line following for this task started
+pub fn after_distance(distance: f32) -> TaskFunc<bool> {
+    Box::new(move |robo: &mut Robot| {
+        Ok(robo.pose().distance - robo.follow_started_distance >= distance)
+    })
+}
+
+/// `cond`, but true at the latest after `timeout`
+pub fn timeout(timeout: Duration, cond: TaskFunc<bool>) -> TaskFunc<bool> {
+    or(cond, after_delay(timeout))
+}
+
+/// A condition as written in a mission file: a name (`--list-tasks`) or a combinator.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum CondDef {
+    Name(String),
+    Combinator(Combinator),
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Combinator {
+    And(Vec<CondDef>),
+    Or(Vec<CondDef>),
+    Not(Box<CondDef>),
+    Consecutive { count: u32, cond: Box<CondDef> },
+    /// ms
+    AfterDelay(u64),
+    /// cm
+    AfterDistance(f32),
+    Timeout { ms: u64, cond: Box<CondDef> },
+}
+
+impl From<&str> for CondDef {
+    fn from(name: &str) -> Self {
+        CondDef::Name(name.to_string())
+    }
+}
+
+impl CondDef {
+    pub fn build(&self, registry: &Registry) -> Ev3Result<TaskFunc<bool>> {
+        let combinator = match self {
+            CondDef::Name(name) => {
+                return registry.get_cond(name).ok_or(Ev3Error::InternalError {
+                    msg: format!("unknown condition `{}`, see --list-tasks", name),
+                })
+            }
+            CondDef::Combinator(combinator) => combinator,
+        };
+        Ok(match combinator {
+            Combinator::And(conds) => fold(conds, registry, and)?,
+            Combinator::Or(conds) => fold(conds, registry, or)?,
+            Combinator::Not(cond) => not(cond.build(registry)?),
+            Combinator::Consecutive { count, cond } => consecutive(*count, cond.build(registry)?),
+            Combinator::AfterDelay(ms) => after_delay(Duration::from_millis(*ms)),
+            Combinator::AfterDistance(distance) => after_distance(*distance),
+            Combinator::Timeout { ms, cond } => {
+                timeout(Duration::from_millis(*ms), cond.build(registry)?)
+            }
+        })
+    }
+}
+
+fn fold(
+    conds: &[CondDef],
+    registry: &Registry,
+    combine: fn(TaskFunc<bool>, TaskFunc<bool>) -> TaskFunc<bool>,
+) -> Ev3Result<TaskFunc<bool>> {
+    let mut conds = conds.iter().map(|cond| cond.build(registry));
+    let first = conds.next().ok_or(Ev3Error::InternalError {
+        msg: "`and`/`or` need at least one condition".to_string(),
+    })??;
+    conds.try_fold(first, |all, cond| Ok(combine(all, cond?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// true on the ticks in `pattern`, the state is the tick number
+    fn ticks(pattern: &'static [bool]) -> CondFunc<usize> {
+        Box::new(move |tick: &mut usize| Ok(pattern[*tick]))
+    }
+
+    fn run(cond: CondFunc<usize>, count: usize) -> Vec<bool> {
+        (0..count).map(|mut tick| cond(&mut tick).unwrap()).collect()
+    }
+
+    #[test]
+    fn logic() {
+        let a = &[true, true, false, false];
+        let b = &[true, false, true, false];
+        assert_eq!(run(and(ticks(a), ticks(b)), 4), [true, false, false, false]);
+        assert_eq!(run(or(ticks(a), ticks(b)), 4), [true, true, true, false]);
+        assert_eq!(run(not(ticks(a)), 4), [false, false, true, true]);
+    }
+
+    #[test]
+    fn consecutive_resets() {
+        let cond = consecutive(2, ticks(&[true, false, true, true, true, false]));
+        assert_eq!(run(cond, 6), [false, false, false, true, true, false]);
+    }
+
+    #[test]
+    fn and_evaluates_both() {
+        // `consecutive` has to see the first tick even though the other side is false
+        let cond = and(ticks(&[false, true]), consecutive(2, ticks(&[true, true])));
+        assert_eq!(run(cond, 2), [false, true]);
+    }
+
+    fn parse(yaml: &str) -> CondDef {
+        #[derive(Deserialize)]
+        struct Step {
+            cond: CondDef,
+        }
+        config::Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize::<Step>()
+            .unwrap()
+            .cond
+    }
+
+    #[test]
+    fn parse_name() {
+        assert_eq!(parse("cond: dist"), "dist".into());
+    }
+
+    #[test]
+    fn parse_combinators() {
+        assert_eq!(
+            parse("cond: {and: [dist, {not: lines}, {after_distance: 50}]}"),
+            CondDef::Combinator(Combinator::And(vec![
+                "dist".into(),
+                CondDef::Combinator(Combinator::Not(Box::new("lines".into()))),
+                CondDef::Combinator(Combinator::AfterDistance(50.0)),
+            ]))
+        );
+        assert_eq!(
+            parse("cond:\n  timeout:\n    ms: 3000\n    cond:\n      consecutive: {count: 3, cond: dist}"),
+            CondDef::Combinator(Combinator::Timeout {
+                ms: 3000,
+                cond: Box::new(CondDef::Combinator(Combinator::Consecutive {
+                    count: 3,
+                    cond: Box::new("dist".into()),
+                })),
+            })
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use crate::robo::{Direction, LineAction};
+use crate::settings::Course;
+use std::io::Write;
+
+/// One line in a course profile file: `distance;kind;name`
+#[derive(Clone, Debug, PartialEq)]
+pub enum CourseEvent {
+    /// `follow_line_once` switched to a new action
+    Line { distance: f32, action: String },
+    /// a task condition triggered
+    Task { distance: f32, name: String },
+}
+
+pub fn action_name(action: &LineAction) -> &'static str {
+    match action {
+        LineAction::Forward | LineAction::Keep => "forward",
+        LineAction::Arc {
+            direction: Direction::Left,
+            ..
+        } => "arc_left",
+        LineAction::Arc {
+            direction: Direction::Right,
+            ..
+        } => "arc_right",
+        LineAction::Pivot {
+            direction: Direction::Left,
+        } => "pivot_left",
+        LineAction::Pivot {
+            direction: Direction::Right,
+        } => "pivot_right",
+        LineAction::Search => "search",
+    }
+}
+
+/// Records the line following decisions of a run.
+#[derive(Default)]
+pub struct CourseRecorder {
+    pub events: Vec<CourseEvent>,
+    last_action: Option<&'static str>,
+}
+
+impl CourseRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_action(&mut self, distance: f32, action: &LineAction) {
+        let name = action_name(action);
+        if self.last_action != Some(name) {
+            self.last_action = Some(name);
+            self.events.push(CourseEvent::Line {
+                distance,
+                action: name.to_string(),
+            });
+        }
+    }
+
+    pub fn record_task(&mut self, distance: f32, name: &str) {
+        self.events.push(CourseEvent::Task {
+            distance,
+            name: name.to_string(),
+        });
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        writeln!(file, "# distance;kind;name")?;
+        for event in &self.events {
+            match event {
+                CourseEvent::Line { distance, action } => {
+                    writeln!(file, "{:.1};line;{}", distance, action)?
+                }
+                CourseEvent::Task { distance, name } => {
+                    writeln!(file, "{:.1};task;{}", distance, name)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A course learned from a previous run.
+#[derive(Debug, Default)]
+pub struct CourseProfile {
+    /// (start, end) in cm of the sections where the robot had to steer
+    pub curves: Vec<(f32, f32)>,
+    /// (distance, task name) where a task condition triggered
+    pub tasks: Vec<(f32, String)>,
+}
+
+impl CourseProfile {
+    pub fn load(path: &std::path::Path, merge_gap: f32) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut events = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid course line: `{}`", line),
+                )
+            };
+            let mut parts = line.splitn(3, ';');
+            let distance: f32 = parts
+                .next()
+                .and_then(|d| d.parse().ok())
+                .ok_or_else(invalid)?;
+            let kind = parts.next().ok_or_else(invalid)?;
+            let name = parts.next().ok_or_else(invalid)?.to_string();
+            events.push(match kind {
+                "line" => CourseEvent::Line {
+                    distance,
+                    action: name,
+                },
+                "task" => CourseEvent::Task { distance, name },
+                _ => return Err(invalid()),
+            });
+        }
+        Ok(Self::from_events(&events, merge_gap))
+    }
+
+    pub fn from_events(events: &[CourseEvent], merge_gap: f32) -> Self {
+        let mut profile = Self::default();
+        let mut curve_start: Option<f32> = None;
+        for event in events {
+            match event {
+                CourseEvent::Line { distance, action } => {
+                    let distance = *distance;
+                    match (action == "forward", curve_start) {
+                        (false, None) => curve_start = Some(distance),
+                        (true, Some(start)) => {
+                            curve_start = None;
+                            match profile.curves.last_mut() {
+                                Some(last) if start - last.1 <= merge_gap => last.1 = distance,
+                                _ => profile.curves.push((start, distance)),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                CourseEvent::Task { distance, name } => {
+                    profile.tasks.push((*distance, name.clone()))
+                }
+            }
+        }
+        if let Some(start) = curve_start {
+            profile.curves.push((start, f32::INFINITY));
+        }
+        profile
+    }
+
+    pub fn in_curve(&self, distance: f32, lookahead: f32) -> bool {
+        self.curves
+            .iter()
+            .any(|&(start, end)| distance >= start - lookahead && distance <= end)
+    }
+
+    pub fn speed_at(&self, distance: f32, settings: &Course) -> i32 {
+        if self.in_curve(distance, settings.lookahead) {
+            settings.curve_speed
+        } else {
+            settings.straight_speed
+        }
+    }
+
+    pub fn task_distance(&self, name: &str) -> Option<f32> {
+        self.tasks
+            .iter()
+            .find(|(_, task)| task == name)
+            .map(|(distance, _)| *distance)
+    }
+
+    /// whether the condition of the task `name` should be checked at `distance`,
+    /// tasks that were never seen are always armed
+    pub fn task_armed(&self, name: &str, distance: f32, arm_window: f32) -> bool {
+        match self.task_distance(name) {
+            Some(task_distance) => distance >= task_distance - arm_window,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Course {
+        Course {
+            lookahead: 10.0,
+            merge_gap: 5.0,
+            arm_window: 20.0,
+            curve_speed: 60,
+            straight_speed: 100,
+        }
+    }
+
+    fn line(distance: f32, action: &str) -> CourseEvent {
+        CourseEvent::Line {
+            distance,
+            action: action.to_string(),
+        }
+    }
+
+    #[test]
+    fn curves_merge_within_gap() {
+        let profile = CourseProfile::from_events(
+            &[
+                line(0.0, "forward"),
+                line(10.0, "arc_left"),
+                line(20.0, "forward"),
+                // 3 cm after the last one, merged
+                line(23.0, "arc_right"),
+                line(30.0, "forward"),
+                // 20 cm after the last one, a new curve
+                line(50.0, "pivot_left"),
+                line(55.0, "forward"),
+                line(80.0, "arc_left"),
+            ],
+            settings().merge_gap,
+        );
+        assert_eq!(
+            profile.curves,
+            [(10.0, 30.0), (50.0, 55.0), (80.0, f32::INFINITY)]
+        );
+    }
+
+    #[test]
+    fn speed_looks_ahead() {
+        let settings = settings();
+        let profile = CourseProfile {
+            curves: vec![(50.0, 70.0)],
+            tasks: Vec::new(),
+        };
+        assert_eq!(profile.speed_at(30.0, &settings), 100);
+        assert_eq!(profile.speed_at(40.0, &settings), 60);
+        assert_eq!(profile.speed_at(70.0, &settings), 60);
+        assert_eq!(profile.speed_at(71.0, &settings), 100);
+    }
+
+    #[test]
+    fn tasks_armed_near_their_distance() {
+        let profile = CourseProfile::from_events(
+            &[CourseEvent::Task {
+                distance: 100.0,
+                name: "turn".to_string(),
+            }],
+            settings().merge_gap,
+        );
+        let arm_window = settings().arm_window;
+        assert!(!profile.task_armed("turn", 70.0, arm_window));
+        assert!(profile.task_armed("turn", 80.0, arm_window));
+        assert!(profile.task_armed("turn", 150.0, arm_window));
+        // never seen
+        assert!(profile.task_armed("stop", 0.0, arm_window));
+    }
+}
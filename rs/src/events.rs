@@ -0,0 +1,317 @@
+//! What happens during a run, published to pluggable subscribers
+//! (console, log file, mqtt, sound) instead of printing in the task functions.
+
+use crate::behaviour_tree::Status;
+use crate::obstacle::ObstacleClass;
+use ev3dev_lang_rust::sound;
+use rumqttc::{Client, MqttOptions, QoS};
+use serde_derive::Deserialize;
+use std::io::Write;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    TaskStarted {
+        name: String,
+    },
+    TaskFinished {
+        name: String,
+        duration: Duration,
+        attempts: u32,
+    },
+    /// the success check failed, `attempt` of `attempts` starts after the recovery
+    TaskRetry {
+        name: String,
+        attempt: u32,
+        attempts: u32,
+    },
+    TaskFailed {
+        name: String,
+        error: String,
+    },
+    /// the condition of a task became true, with the sensor values at that moment
+    ConditionTriggered {
+        name: String,
+        distance: f32,
+        lines: [bool; 3],
+    },
+    /// none of the line sensors sees the line anymore
+    LineLost,
+    /// the middle sensor crossed stripes, see `marker`
+    MarkerDetected {
+        /// cm
+        stripes: Vec<f32>,
+        /// the first matching pattern
+        pattern: String,
+    },
+    /// the condition of a monitor became true
+    MonitorTriggered {
+        name: String,
+        task: Option<String>,
+    },
+    /// a state machine like `PushBlockMachine` entered `state`
+    StateEntered {
+        machine: &'static str,
+        state: String,
+    },
+    StateExited {
+        machine: &'static str,
+        state: String,
+        duration: Duration,
+    },
+    /// `state` took too long, the machine continues with its fallback
+    StateTimedOut {
+        machine: &'static str,
+        state: String,
+    },
+    TreeStarted {
+        name: String,
+    },
+    TreeFinished {
+        name: String,
+        status: Status,
+    },
+    /// a `retry` node starts its child again
+    TreeRetry {
+        attempt: u32,
+        attempts: u32,
+    },
+    /// `width` in cm if there was a sweep
+    ObstacleClassified {
+        class: ObstacleClass,
+        distance: f32,
+        width: Option<f32>,
+    },
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::TaskStarted { name } => write!(f, "Task {} started", name),
+            Event::TaskFinished {
+                name,
+                duration,
+                attempts,
+            } => write!(
+                f,
+                "Task {} finished after {:.2} s in {} attempt(s)",
+                name,
+                duration.as_secs_f32(),
+                attempts
+            ),
+            Event::TaskRetry {
+                name,
+                attempt,
+                attempts,
+            } => write!(f, "Task {} retry, attempt {}/{}", name, attempt, attempts),
+            Event::TaskFailed { name, error } => write!(f, "Task {} failed: {}", name, error),
+            Event::ConditionTriggered {
+                name,
+                distance,
+                lines,
+            } => write!(
+                f,
+                "Condition of {} triggered at {:.1} cm, lines {:?}",
+                name, distance, lines
+            ),
+            Event::LineLost => write!(f, "Line lost"),
+            Event::MarkerDetected { stripes, pattern } => write!(
+                f,
+                "Marker {} detected, stripes {} cm",
+                pattern,
+                stripes
+                    .iter()
+                    .map(|width| format!("{:.1}", width))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Event::MonitorTriggered { name, task } => match task {
+                Some(task) => write!(f, "Monitor {} triggered during {}", name, task),
+                None => write!(f, "Monitor {} triggered", name),
+            },
+            Event::StateEntered { machine, state } => write!(f, "{}: enter {}", machine, state),
+            Event::StateExited {
+                machine,
+                state,
+                duration,
+            } => write!(
+                f,
+                "{}: exit {} after {} ms",
+                machine,
+                state,
+                duration.as_millis()
+            ),
+            Event::StateTimedOut { machine, state } => {
+                write!(f, "{}: {} timed out", machine, state)
+            }
+            Event::TreeStarted { name } => write!(f, "Tree {} started", name),
+            Event::TreeFinished { name, status } => {
+                write!(f, "Tree {} finished: {:?}", name, status)
+            }
+            Event::TreeRetry { attempt, attempts } => {
+                write!(f, "Tree retry, attempt {}/{}", attempt, attempts)
+            }
+            Event::ObstacleClassified {
+                class,
+                distance,
+                width,
+            } => match width {
+                Some(width) => write!(
+                    f,
+                    "Obstacle at {:.1} cm is a {:?} ({:.1} cm wide)",
+                    distance, class, width
+                ),
+                None => write!(f, "Obstacle at {:.1} cm is a {:?}", distance, class),
+            },
+        }
+    }
+}
+
+pub trait Subscriber: Send {
+    fn handle(&mut self, event: &Event);
+}
+
+/// `settings.events`, which subscribers `EventBus::from_settings` adds
+#[derive(Debug, Default, Deserialize)]
+pub struct EventSettings {
+    pub console: bool,
+    /// log file, one "ms since start;event" per line
+    pub file: Option<std::path::PathBuf>,
+    pub sound: bool,
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Box<dyn Subscriber>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_settings(settings: &EventSettings) -> std::io::Result<Self> {
+        let mut bus = Self::new();
+        if settings.console {
+            bus.subscribe(Box::new(Console));
+        }
+        if let Some(file) = &settings.file {
+            bus.subscribe(Box::new(FileLog::new(file)?));
+        }
+        if settings.sound {
+            bus.subscribe(Box::new(Sound::new()));
+        }
+        Ok(bus)
+    }
+
+    pub fn subscribe(&mut self, subscriber: Box<dyn Subscriber>) {
+        self.subscribers.push(subscriber);
+    }
+
+    pub fn publish(&mut self, event: Event) {
+        for subscriber in &mut self.subscribers {
+            subscriber.handle(&event);
+        }
+    }
+}
+
+pub struct Console;
+
+impl Subscriber for Console {
+    fn handle(&mut self, event: &Event) {
+        println!("{}", event);
+    }
+}
+
+pub struct FileLog {
+    file: std::fs::File,
+    started: Instant,
+}
+
+impl FileLog {
+    pub fn new(path: &std::path::Path) -> std::io::Result<Self> {
+        Ok(Self {
+            file: std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+            started: Instant::now(),
+        })
+    }
+}
+
+impl Subscriber for FileLog {
+    fn handle(&mut self, event: &Event) {
+        let _ = writeln!(
+            self.file,
+            "{};{}",
+            self.started.elapsed().as_millis(),
+            event
+        );
+    }
+}
+
+/// publishes every event as text to "robo/event"
+pub struct Mqtt {
+    client: Client,
+}
+
+impl Mqtt {
+    pub fn new(addr: String) -> Self {
+        let (client, mut connection) =
+            Client::new(MqttOptions::new("HerrBert-events", addr, 1883), 10);
+        std::thread::spawn(move || for _notification in connection.iter() {});
+        Self { client }
+    }
+}
+
+impl Subscriber for Mqtt {
+    fn handle(&mut self, event: &Event) {
+        // never block the control loop, drop events while the queue is full
+        let _ = self
+            .client
+            .try_publish("robo/event", QoS::AtMostOnce, false, event.to_string());
+    }
+}
+
+/// high beep when a task finishes, low tone when it fails or the line is lost,
+/// played one after another by a player thread that waits for each tone
+pub struct Sound {
+    tones: SyncSender<(f32, i32)>,
+}
+
+impl Sound {
+    pub fn new() -> Self {
+        let (tones, queue) = sync_channel::<(f32, i32)>(4);
+        std::thread::spawn(move || {
+            for (frequency, duration) in queue {
+                if let Ok(mut child) = sound::tone(frequency, duration) {
+                    let _ = child.wait();
+                }
+            }
+        });
+        Self { tones }
+    }
+}
+
+impl Default for Sound {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Subscriber for Sound {
+    fn handle(&mut self, event: &Event) {
+        let tone = match event {
+            Event::TaskFinished { .. } => (880.0, 100),
+            Event::TaskFailed { .. } => (220.0, 400),
+            Event::TaskRetry { .. } | Event::TreeRetry { .. } => (440.0, 100),
+            Event::LineLost => (330.0, 100),
+            Event::MarkerDetected { .. } => (660.0, 50),
+            _ => return,
+        };
+        // never block the control loop, drop tones while the player is behind
+        let _ = self.tones.try_send(tone);
+    }
+}
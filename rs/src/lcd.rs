@@ -0,0 +1,190 @@
+//! Text on the EV3 display, written straight to the Linux framebuffer.
+
+use ev3dev_lang_rust::{Ev3Error, Ev3Result};
+use std::io::{Seek, SeekFrom, Write};
+
+const FB_DEVICE: &str = "/dev/fb0";
+const FB_SYSFS: &str = "/sys/class/graphics/fb0";
+const CHAR_WIDTH: usize = 6;
+const LINE_HEIGHT: usize = 10;
+
+/// 5x7 glyphs for ' ' to '_', one byte per column, lowest bit at the top
+const FONT: [[u8; 5]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x56, 0x20, 0x50], // &
+    [0x00, 0x08, 0x07, 0x03, 0x00], // '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // )
+    [0x2A, 0x1C, 0x7F, 0x1C, 0x2A], // *
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // +
+    [0x00, 0x80, 0x70, 0x30, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x00, 0x60, 0x60, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // 0
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // 1
+    [0x72, 0x49, 0x49, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x49, 0x4D, 0x33], // 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x31], // 6
+    [0x41, 0x21, 0x11, 0x09, 0x07], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x46, 0x49, 0x49, 0x29, 0x1E], // 9
+    [0x00, 0x00, 0x14, 0x00, 0x00], // :
+    [0x00, 0x40, 0x34, 0x00, 0x00], // ;
+    [0x00, 0x08, 0x14, 0x22, 0x41], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x59, 0x09, 0x06], // ?
+    [0x3E, 0x41, 0x5D, 0x59, 0x4E], // @
+    [0x7C, 0x12, 0x11, 0x12, 0x7C], // A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7F, 0x41, 0x41, 0x41, 0x3E], // D
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3E, 0x41, 0x41, 0x51, 0x73], // G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // H
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // J
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7F, 0x02, 0x1C, 0x02, 0x7F], // M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // N
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // R
+    [0x26, 0x49, 0x49, 0x49, 0x32], // S
+    [0x03, 0x01, 0x7F, 0x01, 0x03], // T
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // V
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x03, 0x04, 0x78, 0x04, 0x03], // Y
+    [0x61, 0x59, 0x49, 0x4D, 0x43], // Z
+    [0x00, 0x7F, 0x41, 0x41, 0x41], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x41, 0x7F], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+];
+
+/// Draws into a buffer, `flush` shows it. Only upper case, lower case letters are converted.
+pub struct Lcd {
+    file: std::fs::File,
+    width: usize,
+    height: usize,
+    stride: usize,
+    bits_per_pixel: usize,
+    pixels: Vec<u8>,
+}
+
+impl Lcd {
+    pub fn new() -> Ev3Result<Self> {
+        let (width, height) = match read_sysfs("virtual_size")?.split_once(',') {
+            Some((width, height)) => (parse(width)?, parse(height)?),
+            None => {
+                return Err(Ev3Error::InternalError {
+                    msg: "invalid framebuffer size".to_string(),
+                })
+            }
+        };
+        let bits_per_pixel = parse(&read_sysfs("bits_per_pixel")?)?;
+        let stride = parse(&read_sysfs("stride")?)?;
+        Ok(Self {
+            file: std::fs::OpenOptions::new().write(true).open(FB_DEVICE)?,
+            width,
+            height,
+            stride,
+            bits_per_pixel,
+            pixels: vec![0; stride * height],
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.height / LINE_HEIGHT
+    }
+
+    pub fn columns(&self) -> usize {
+        self.width / CHAR_WIDTH
+    }
+
+    pub fn clear(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.set_pixel(x, y, false);
+            }
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, black: bool) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        if self.bits_per_pixel == 1 {
+            // 1 is black, leftmost pixel in the lowest bit
+            let byte = &mut self.pixels[y * self.stride + x / 8];
+            if black {
+                *byte |= 1 << (x % 8);
+            } else {
+                *byte &= !(1 << (x % 8));
+            }
+        } else {
+            let bytes = self.bits_per_pixel / 8;
+            let start = y * self.stride + x * bytes;
+            for byte in &mut self.pixels[start..start + bytes] {
+                *byte = if black { 0x00 } else { 0xFF };
+            }
+        }
+    }
+
+    /// write `text` to line `row`, `inverted` draws white on black (e.g. the selected entry)
+    pub fn text(&mut self, row: usize, text: &str, inverted: bool) {
+        let top = row * LINE_HEIGHT;
+        if inverted {
+            for y in top..top + LINE_HEIGHT {
+                for x in 0..self.width {
+                    self.set_pixel(x, y, true);
+                }
+            }
+        }
+        for (column, c) in text.chars().take(self.columns()).enumerate() {
+            let c = c.to_ascii_uppercase() as usize;
+            let glyph = FONT
+                .get(c.wrapping_sub(0x20))
+                .unwrap_or(&FONT['?' as usize - 0x20]);
+            for (dx, bits) in glyph.iter().enumerate() {
+                for dy in 0..8 {
+                    if bits & (1 << dy) != 0 {
+                        self.set_pixel(column * CHAR_WIDTH + dx + 1, top + dy + 1, !inverted);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Ev3Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&self.pixels)?;
+        Ok(())
+    }
+}
+
+fn read_sysfs(attribute: &str) -> Ev3Result<String> {
+    Ok(std::fs::read_to_string(format!("{}/{}", FB_SYSFS, attribute))?
+        .trim()
+        .to_string())
+}
+
+fn parse(value: &str) -> Ev3Result<usize> {
+    value.trim().parse().map_err(|_| Ev3Error::InternalError {
+        msg: format!("invalid framebuffer attribute `{}`", value),
+    })
+}
@@ -1,17 +1,45 @@
 extern crate ev3dev_lang_rust;
 extern crate serde;
 extern crate serde_derive;
+pub mod behaviour_tree;
+pub mod cond;
+pub mod course;
+pub mod events;
+pub mod lcd;
+pub mod marker;
+pub mod menu;
+pub mod mission;
+pub mod monitor;
+pub mod motion;
 pub mod move_steering;
+pub mod obstacle;
+pub mod odometry;
+pub mod push_block;
+pub mod registry;
 pub mod robo;
+pub mod script;
+pub mod selector;
 pub mod sensor;
 pub mod settings;
+pub mod splits;
 use clap::Parser;
+use cond::CondDef;
+use behaviour_tree::BehaviourTree;
+use course::{CourseProfile, CourseRecorder};
+use events::EventBus;
+use mission::Mission;
 use move_steering::MoveSteering;
-use robo::{Robot, Task};
-use sensor::{LineSensor, LineSensorType};
+use obstacle::ObstacleClass;
+use odometry::Pose;
+use push_block::PushBlockMachine;
+use registry::Registry;
+use robo::{Hardware, Robot, Task};
+use sensor::{Gyro, LineSensor, LineSensorType};
 
 use ev3dev_lang_rust::motors::{LargeMotor, MotorPort};
-use ev3dev_lang_rust::sensors::{ColorSensor, LightSensor, Sensor, SensorPort, UltrasonicSensor};
+use ev3dev_lang_rust::sensors::{
+    ColorSensor, GyroSensor, LightSensor, Sensor, SensorPort, UltrasonicSensor,
+};
 use ev3dev_lang_rust::sound;
 use ev3dev_lang_rust::Ev3Button;
 use ev3dev_lang_rust::Ev3Result;
@@ -97,27 +125,31 @@ struct Args {
     #[arg(long, short, value_parser = settings::parse_key_val::<String, String>, action = clap::ArgAction::Append)]
     override_: Vec<(String, String)>,
 
+    /// show the menu on the brick display before starting (settings, calibration, mission)
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    menu: bool,
+
     /// calibrate sensors
     #[arg(long, action = clap::ArgAction::SetTrue)]
     calibrate: bool,
 
-    /// execute turn action
+    /// execute the turn step of missions/all.yaml
     #[arg(long, action = clap::ArgAction::SetTrue)]
     turn: bool,
 
-    /// execute wait for ball action
+    /// execute the wait for ball step of missions/all.yaml
     #[arg(long, action = clap::ArgAction::SetTrue)]
     wait_for_ball: bool,
 
-    /// execute push block action
+    /// execute the push block step of missions/all.yaml
     #[arg(long, action = clap::ArgAction::SetTrue)]
     push_block: bool,
 
-    /// execute throw ball action
+    /// execute the throw ball step of missions/all.yaml
     #[arg(long, action = clap::ArgAction::SetTrue)]
     throw_ball: bool,
 
-    /// execute all actions (turn, wait for ball, push block, throw ball)
+    /// execute all steps of missions/all.yaml, the same as --mission missions/all.yaml
     #[arg(long, action = clap::ArgAction::SetTrue)]
     all: bool,
 
@@ -125,6 +157,19 @@ struct Args {
     #[arg(long, action = clap::ArgAction::SetTrue)]
     none: bool,
 
+    /// execute a task, format: action:cond
+    /// example: --task turn:cond_dist
+    #[arg(long, value_parser = registry::parse_task, action = clap::ArgAction::Append)]
+    task: Vec<(String, String)>,
+
+    /// load actions and conditions from a Rhai script, in addition to the ones in scripts/
+    #[arg(long, value_hint = clap::ValueHint::FilePath, action = clap::ArgAction::Append)]
+    script: Vec<std::path::PathBuf>,
+
+    /// list the actions and conditions for --task and mission files
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    list_tasks: bool,
+
     /// publish sensor values to mqtt
     #[arg(long, action = clap::ArgAction::SetTrue)]
     mqtt: bool,
@@ -141,18 +186,92 @@ struct Args {
     #[arg(long, action = clap::ArgAction::SetTrue)]
     stop_dist: bool,
 
-    /// party
+    /// execute the party step of missions/all.yaml
     #[arg(long, action = clap::ArgAction::SetTrue)]
     party: bool,
+
+    /// record the line following decisions of this run to a course profile
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    record_course: Option<std::path::PathBuf>,
+
+    /// run the steps of a mission file (e.g. missions/all.yaml) instead of the task flags
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    mission: Option<std::path::PathBuf>,
+
+    /// run a behaviour tree file (e.g. trees/push_block.yaml), trees in trees/ are also
+    /// available as the actions tree_<file name> in --task and mission files
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    tree: Option<std::path::PathBuf>,
+
+    /// skip the steps of --mission, --task or --all before this one (name, action or number)
+    #[arg(long)]
+    start_at: Option<String>,
+
+    /// choose the first step with the brick buttons before starting,
+    /// up/down cycle through the steps, the centre button starts
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    select_start: bool,
+
+    /// course profile of a previous run, used to slow down before curves
+    /// and to only check task conditions near their known position
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    course: Option<std::path::PathBuf>,
+}
+
+/// index of the first step to run, `steps` are (name, action)
+/// actions of the `mission::ALL` steps chosen with the task flags
+fn flag_actions(args: &Args) -> Vec<&'static str> {
+    [
+        (args.turn, "turn"),
+        (args.wait_for_ball, "wait_for_ball"),
+        (args.push_block, "push_block"),
+        (args.throw_ball, "throw_ball"),
+        (args.party, "party"),
+    ]
+    .into_iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, action)| action)
+    .collect()
+}
+
+fn start_step(args: &Args, steps: &[(String, String)]) -> Ev3Result<usize> {
+    let start = match &args.start_at {
+        Some(start_at) => selector::find_step(steps, start_at)?,
+        None => 0,
+    };
+    if args.select_start && !steps.is_empty() {
+        selector::select_step(steps, start)
+    } else {
+        Ok(start)
+    }
+}
+
+/// `Registry::task` with the budget and retry policy of `action` from `settings.act`
+fn build_task(
+    registry: &Registry,
+    settings: &settings::Settings,
+    name: &str,
+    action: &str,
+    cond: &CondDef,
+) -> Ev3Result<Task> {
+    let task = registry
+        .task(name, action, cond)?
+        .with_budget(settings.act.budget(action));
+    Ok(match settings.act.retry(action) {
+        Some(policy) => task.with_retry(registry.retry(policy)?),
+        None => task,
+    })
 }
 
 fn act_turn(robo: &mut Robot) -> Ev3Result<()> {
-    robo.steering.on_for_rotations(
-        robo.settings.act.turn.steering,
-        robo.settings.act.turn.speed,
-        robo.settings.act.turn.rotations,
-        true,
-    )?;
+    robo.with_ramp(robo.settings.act.turn.ramp, |robo| {
+        robo.turn(
+            robo.settings.act.turn.steering,
+            robo.settings.act.turn.speed,
+            robo.settings.act.turn.rotations,
+            robo.settings.act.turn.angle,
+        )
+    })?;
     robo.steering.on(
         robo.settings.act.turn.steering,
         robo.settings.act.turn.speed,
@@ -176,11 +295,14 @@ fn act_wait_for_ball(robo: &mut Robot) -> Ev3Result<()> {
     robo.steering.off()?;
     robo.steering.wait_until_not_moving()?;
     while robo.us_sensor.get_distance_centimeters()? < robo.settings.act.wait_for_ball.finish_dist {
+        robo.check_budget()?;
         std::thread::sleep(std::time::Duration::from_millis(10));
     }
     Ok(())
 }
 
+/// more than `cond.lines.count` transitions of the middle sensor in the last
+/// `cond.lines.time` ms, whatever their widths, unlike `marker` which needs known ones
 fn cond_lines(robo: &mut Robot) -> Ev3Result<bool> {
     Ok(robo
         .middle_sensor
@@ -200,104 +322,7 @@ fn cond_lines(robo: &mut Robot) -> Ev3Result<bool> {
 }
 
 fn act_push_block(robo: &mut Robot) -> Ev3Result<()> {
-    // drive 90° to the right
-    robo.steering.on_for_rotations(
-        robo.settings
-            .act
-            .push_block
-            .leave_line
-            .on_for_rotations
-            .steering,
-        robo.settings
-            .act
-            .push_block
-            .leave_line
-            .on_for_rotations
-            .speed,
-        robo.settings
-            .act
-            .push_block
-            .leave_line
-            .on_for_rotations
-            .rotations,
-        true,
-    )?;
-    //std::thread::sleep(std::time::Duration::from_millis(1000));
-    robo.steering.on(
-        robo.settings.act.push_block.leave_line.drive.steering,
-        robo.settings.act.push_block.leave_line.drive.speed,
-    )?;
-    robo.middle_sensor
-        .wait_for_line(false, std::time::Duration::from_millis(250))?;
-    robo.steering.off()?;
-    //std::thread::sleep(std::time::Duration::from_millis(3000));
-    // drive to the right until the line is lost
-    while robo.us_sensor.get_distance_centimeters()? > robo.settings.act.push_block.drive_until_dist
-    {
-        robo.follow_line_once()?;
-    }
-    //robo.steering.off()?;
-    println!("pushing block");
-    //std::thread::sleep(std::time::Duration::from_millis(100));
-    while robo.us_sensor.get_distance_centimeters()?
-        <= robo.settings.act.push_block.drive_until_dist
-    {
-        robo.follow_line_once()?;
-    }
-    robo.steering.off()?;
-    println!("block pushed");
-    std::thread::sleep(std::time::Duration::from_millis(100));
-    println!("turning");
-    // 180° turn
-    robo.steering.on_for_rotations(
-        robo.settings
-            .act
-            .push_block
-            .u_turn
-            .on_for_rotations
-            .steering,
-        robo.settings.act.push_block.u_turn.on_for_rotations.speed,
-        robo.settings
-            .act
-            .push_block
-            .u_turn
-            .on_for_rotations
-            .rotations,
-        true,
-    )?;
-    robo.steering.on(
-        robo.settings.act.push_block.u_turn.drive.steering,
-        robo.settings.act.push_block.u_turn.drive.speed,
-    )?;
-    robo.middle_sensor
-        .wait_for_line(false, std::time::Duration::from_millis(500))?;
-    //robo.steering.off()?;
-    println!("turning done");
-    //std::thread::sleep(std::time::Duration::from_millis(3000));
-    println!("drive until line lost");
-    // drive to the right until the line is lost
-    while robo.left_sensor.on_line() || robo.middle_sensor.on_line() || robo.right_sensor.on_line()
-    {
-        robo.follow_line_once()?;
-    }
-    //robo.steering.off()?;
-    println!("line lost");
-    //std::thread::sleep(std::time::Duration::from_millis(3000));
-
-    // drive 90° to the right
-    robo.steering.on_for_seconds(
-        robo.settings.act.push_block.return_to_line.steering,
-        robo.settings.act.push_block.return_to_line.speed,
-        robo.settings.act.push_block.return_to_line.seconds,
-        true
-    )?;
-    //println!("on for seconds done in:");
-    //println!("{}", started.elapsed().as_millis());
-    //std::thread::sleep(std::time::Duration::from_millis(20));
-    //robo.middle_sensor
-    //    .wait_for_line(false, std::time::Duration::from_millis(1000))?;
-    //robo.steering.off()?;
-    Ok(())
+    PushBlockMachine::new().run(robo)
 }
 
 fn act_stop(robo: &mut Robot) -> Ev3Result<()> {
@@ -331,6 +356,63 @@ fn act_throw_ball(robo: &mut Robot) -> Ev3Result<()> {
     Ok(())
 }
 
+fn cond_marker(robo: &mut Robot) -> Ev3Result<bool> {
+    marker::matches(robo, None)
+}
+
+fn cond_wall(robo: &mut Robot) -> Ev3Result<bool> {
+    obstacle::is_class(robo, ObstacleClass::Wall)
+}
+
+fn cond_barrier(robo: &mut Robot) -> Ev3Result<bool> {
+    obstacle::is_class(robo, ObstacleClass::Barrier)
+}
+
+fn cond_block(robo: &mut Robot) -> Ev3Result<bool> {
+    obstacle::is_class(robo, ObstacleClass::Block)
+}
+
+fn cond_obstacle(robo: &mut Robot) -> Ev3Result<bool> {
+    Ok(robo.us_sensor.get_distance_centimeters()? < robo.settings.cond.obstacle)
+}
+
+fn cond_low_battery(robo: &mut Robot) -> Ev3Result<bool> {
+    let volts = robo.power.get_voltage_now()? as f32 / 1000000.0;
+    Ok(volts < robo.settings.cond.battery)
+}
+
+fn cond_on_line(robo: &mut Robot) -> Ev3Result<bool> {
+    Ok(robo.left_sensor.on_line() || robo.middle_sensor.on_line() || robo.right_sensor.on_line())
+}
+
+fn act_search_line(robo: &mut Robot) -> Ev3Result<()> {
+    robo.steering
+        .spin(robo.last_turn.sign() * robo.settings.act.search_line.speed)?;
+    let res = robo.middle_sensor.wait_for_line(
+        false,
+        std::time::Duration::from_millis(robo.settings.act.search_line.timeout),
+    );
+    robo.steering.off()?;
+    res
+}
+
+fn act_beep(_robo: &mut Robot) -> Ev3Result<()> {
+    sound::beep()?;
+    Ok(())
+}
+
+fn act_log(robo: &mut Robot) -> Ev3Result<()> {
+    let pose = robo.pose();
+    println!(
+        "Pose: x: {:.1} cm, y: {:.1} cm, heading: {:.1}°, distance: {:.1} cm",
+        pose.x,
+        pose.y,
+        pose.heading_degrees(),
+        pose.distance
+    );
+    Ok(())
+}
+
 fn cond_party(robo: &mut Robot) -> Ev3Result<bool> {
     Ok(true)
 }
@@ -338,11 +420,12 @@ fn cond_party(robo: &mut Robot) -> Ev3Result<bool> {
 fn act_party(robo: &mut Robot) -> Ev3Result<()> {
     //sound::play("/home/robot/SDP2022/win.wav")?.wait()?;
     //robo.steering.on_for_rotations(0, -100, 1.0, true)?;
-    robo.steering.on(100, 100)?;
+    robo.steering
+        .tank_on(robo.settings.act.party.left, robo.settings.act.party.right)?;
     robo.ball_motor
         .set_speed_sp(900)?;
     let started = std::time::Instant::now();
-    while started.elapsed() < std::time::Duration::from_secs(20) {
+    while started.elapsed() < std::time::Duration::from_secs_f32(robo.settings.act.party.seconds) {
         robo.ball_motor
             .wait_until_not_moving(Some(std::time::Duration::from_millis(500)));
         robo.ball_motor
@@ -356,7 +439,111 @@ fn act_party(robo: &mut Robot) -> Ev3Result<()> {
     Ok(())
 }
 
-fn mqtt(addr: String) {
+fn registry() -> Registry {
+    let mut registry = Registry::new();
+    registry
+        .action(
+            "turn",
+            "turn around, then search the line",
+            &["act.turn"],
+            act_turn,
+        )
+        .action(
+            "wait_for_ball",
+            "drive to the barrier, wait until it opens",
+            &["act.wait_for_ball"],
+            act_wait_for_ball,
+        )
+        .action(
+            "push_block",
+            "leave the line to the right, push the block away and return to the line",
+            &["act.push_block"],
+            act_push_block,
+        )
+        .action(
+            "throw_ball",
+            "drive against the wall and throw the ball",
+            &["act.throw_ball"],
+            act_throw_ball,
+        )
+        .action("stop", "stop the motors", &[], act_stop)
+        .action(
+            "party",
+            "spin and wave the ball arm",
+            &["act.party", "act.throw_ball.ball_motor_rel_pos"],
+            act_party,
+        )
+        .cond(
+            "dist",
+            "ultrasonic distance below cond.dist",
+            &["cond.dist"],
+            cond_dist,
+        )
+        .cond(
+            "lines",
+            "middle sensor crossed more than cond.lines.count lines in cond.lines.time ms",
+            &["cond.lines"],
+            cond_lines,
+        )
+        .cond("party", "always true", &[], cond_party)
+        .action("beep", "beep once", &[], act_beep)
+        .action(
+            "search_line",
+            "turn on the spot towards the last turn until the middle sensor finds the line",
+            &["act.search_line"],
+            act_search_line,
+        )
+        .cond(
+            "on_line",
+            "any line sensor on the line",
+            &[],
+            cond_on_line,
+        )
+        .action("log", "print the pose", &[], act_log)
+        .cond(
+            "obstacle",
+            "ultrasonic distance below cond.obstacle",
+            &["cond.obstacle"],
+            cond_obstacle,
+        )
+        .cond(
+            "marker",
+            "the middle sensor crossed stripes matching any pattern in markers.patterns",
+            &["markers"],
+            cond_marker,
+        )
+        .cond(
+            "wall",
+            "obstacle closer than cond.dist, classified as wall, stops and turns to both sides to measure it",
+            &["cond.dist", "obstacle"],
+            cond_wall,
+        )
+        .cond(
+            "barrier",
+            "obstacle closer than cond.dist, classified as barrier, stops and turns to both sides to measure it",
+            &["cond.dist", "obstacle"],
+            cond_barrier,
+        )
+        .cond(
+            "block",
+            "obstacle closer than cond.dist, classified as block, stops and turns to both sides to measure it",
+            &["cond.dist", "obstacle"],
+            cond_block,
+        )
+        .cond(
+            "low_battery",
+            "battery voltage below cond.battery",
+            &["cond.battery"],
+            cond_low_battery,
+        );
+    registry
+}
+
+fn mqtt(
+    addr: String,
+    pose: std::sync::Arc<std::sync::Mutex<Pose>>,
+    state: std::sync::Arc<std::sync::Mutex<String>>,
+) {
     std::thread::spawn(move || {
         let mqtt_options = MqttOptions::new("HerrBert", addr, 1883);
         let (mut client, mut connection) = Client::new(mqtt_options, 10);
@@ -499,6 +686,47 @@ fn mqtt(addr: String) {
                         b_motor.get_position().unwrap().to_string(),
                     )
                     .unwrap();
+                let current_pose = *pose.lock().unwrap();
+                client
+                    .publish(
+                        "robo/pose/x",
+                        QoS::AtMostOnce,
+                        false,
+                        current_pose.x.to_string(),
+                    )
+                    .unwrap();
+                client
+                    .publish(
+                        "robo/pose/y",
+                        QoS::AtMostOnce,
+                        false,
+                        current_pose.y.to_string(),
+                    )
+                    .unwrap();
+                client
+                    .publish(
+                        "robo/pose/heading",
+                        QoS::AtMostOnce,
+                        false,
+                        current_pose.heading_degrees().to_string(),
+                    )
+                    .unwrap();
+                client
+                    .publish(
+                        "robo/pose/distance",
+                        QoS::AtMostOnce,
+                        false,
+                        current_pose.distance.to_string(),
+                    )
+                    .unwrap();
+                client
+                    .publish(
+                        "robo/state",
+                        QoS::AtMostOnce,
+                        false,
+                        state.lock().unwrap().clone(),
+                    )
+                    .unwrap();
                 client.publish("robo/psu/amps", QoS::AtMostOnce, false, (psu.get_current_now().unwrap() as f32/1000000.0).to_string()).unwrap();
                 client.publish("robo/psu/volts", QoS::AtMostOnce, false, (psu.get_voltage_now().unwrap()as f32/1000000.0).to_string()).unwrap();
                 std::thread::sleep(std::time::Duration::from_millis(75));
@@ -511,12 +739,32 @@ fn mqtt(addr: String) {
 fn main() -> Ev3Result<()> {
     let started = std::time::Instant::now();
     let args: Args = Args::parse();
-
-    let settings: settings::Settings = settings::Settings::new(
-        args.override_,
-        args.settings.unwrap_or(std::path::PathBuf::from("")),
-    )
-    .unwrap();
+    let mut registry = registry();
+    script::load_all(&mut registry, &args.script);
+
+    let settings_file = args.settings.clone().unwrap_or(std::path::PathBuf::from(""));
+    if args.list_tasks {
+        // the markers come from the settings, which are not there when not on the brick
+        match settings::Settings::new(args.override_.clone(), settings_file.clone()) {
+            Ok(settings) => marker::register(&mut registry, &settings.markers),
+            Err(e) => println!("failed to load the settings, not listing marker_<name>: {}", e),
+        }
+        behaviour_tree::register_all(&mut registry, args.tree.as_slice());
+        registry.print();
+        return Ok(());
+    }
+    let mut overrides = args.override_.clone();
+    let mut mission = args.mission.clone();
+    let mut settings: settings::Settings =
+        settings::Settings::new(overrides.clone(), settings_file.clone()).unwrap();
+    if args.menu {
+        let choice = menu::Menu::new(&settings, mission)?.run(calibrate_sensors)?;
+        overrides.extend(choice.overrides);
+        mission = choice.mission;
+        settings = settings::Settings::new(overrides.clone(), settings_file.clone()).unwrap();
+    }
+    marker::register(&mut registry, &settings.markers);
+    behaviour_tree::register_all(&mut registry, args.tree.as_slice());
 
     let (left_sensor_threshold, middle_sensor_threshold, right_sensor_threshold) = if args.calibrate
     {
@@ -529,64 +777,66 @@ fn main() -> Ev3Result<()> {
         )
     };
 
-    let mut robo = Robot::new(
-        MoveSteering::new(
+    let gyro = match settings.gyro.port {
+        Some(port) => Some(Gyro::new(
+            GyroSensor::get(port.sensor_port())?,
+            settings.gyro.invert,
+        )?),
+        None => None,
+    };
+
+    let hardware = Hardware {
+        steering: MoveSteering::new(
             LargeMotor::get(MotorPort::OutA)?,
             LargeMotor::get(MotorPort::OutD)?,
             settings.steering.stop_action,
+            settings.steering.ramp,
+            settings.steering.sync_gain,
+            settings.steering.model,
+            settings.geometry,
         )?,
-        LineSensor::new(
+        left_sensor: LineSensor::new(
             LineSensorType::Light(LightSensor::get(SensorPort::In1)?),
             left_sensor_threshold,
             false,
         )?,
-        LineSensor::new(
+        right_sensor: LineSensor::new(
             LineSensorType::Light(LightSensor::get(SensorPort::In4)?),
             right_sensor_threshold,
             false,
         )?,
-        LineSensor::new(
+        middle_sensor: LineSensor::new(
             LineSensorType::Color(ColorSensor::get(SensorPort::In3)?),
             middle_sensor_threshold,
             true,
         )?,
-        UltrasonicSensor::get(SensorPort::In2)?,
-        LargeMotor::get(MotorPort::OutB)?,
-        settings,
-    );
-
-    let turn = Task::new(
-        ("turn").to_string(),
-        Box::new(act_turn),
-        Box::new(cond_dist),
-    );
-    let catch_ball = Task::new(
-        ("catch ball").to_string(),
-        Box::new(act_wait_for_ball),
-        Box::new(cond_dist),
-    );
-    let push_block = Task::new(
-        ("push block").to_string(),
-        Box::new(act_push_block),
-        Box::new(cond_lines),
-    );
-    let throw_ball = Task::new(
-        ("throw ball").to_string(),
-        Box::new(act_throw_ball),
-        Box::new(cond_dist),
-    );
+        us_sensor: UltrasonicSensor::get(SensorPort::In2)?,
+        gyro,
+        ball_motor: LargeMotor::get(MotorPort::OutB)?,
+        power: PowerSupply::new()?,
+    };
+    let mut robo = Robot::new(hardware, settings);
 
-    let stop = Task::new(
-        ("stop").to_string(),
-        Box::new(act_stop),
-        Box::new(cond_dist),
-    );
+    if let Some(course) = &args.course {
+        robo.course_profile = Some(
+            CourseProfile::load(course, robo.settings.course.merge_gap)
+                .expect("failed to load course profile"),
+        );
+    }
+    if args.record_course.is_some() {
+        robo.course_recorder = Some(CourseRecorder::new());
+    }
 
-    let party = Task::new(
-        ("party").to_string(),
-        Box::new(act_party),
-        Box::new(cond_party),
-    );
+    // --all and the task flags run (some of) the steps of missions/all.yaml
+    let flag_actions = flag_actions(&args);
+    let from_flags = mission.is_none()
+        && args.tree.is_none()
+        && args.task.is_empty()
+        && (args.all
+            || (!args.none && !args.stop && (args.stop_dist || !flag_actions.is_empty())));
+    if from_flags {
+        mission = Some(std::path::PathBuf::from(mission::ALL));
+    }
 
     ctrlc::set_handler(move || {
         std::thread::sleep(std::time::Duration::from_millis(100));
@@ -604,41 +854,134 @@ fn main() -> Ev3Result<()> {
     })
     .expect("Error setting Ctrl-C handler");
 
+    robo.events = EventBus::from_settings(&robo.settings.events)?;
     if args.mqtt {
-        mqtt(args.mqtt_address.unwrap_or_default())
+        robo.events.subscribe(Box::new(events::Mqtt::new(
+            args.mqtt_address.clone().unwrap_or_default(),
+        )));
+        mqtt(
+            args.mqtt_address.clone().unwrap_or_default(),
+            robo.odometry.shared_pose(),
+            robo.state.clone(),
+        )
     }
 
-    if args.all {
-        robo.follow_line_loop(Some(turn))?;
-        robo.follow_line_loop(Some(catch_ball))?;
-        robo.follow_line_loop(Some(push_block))?;
-        robo.follow_line_loop(Some(throw_ball))?;
-        robo.follow_line_loop(Some(party))?;
-    } else if args.none {
-        robo.follow_line_loop(None)?;
-    } else if args.stop {
-        robo.steering.off()?;
-        robo.steering.wait_until_not_moving()?;
-    } else {
-        if args.turn {
-            robo.follow_line_loop(Some(turn))?;
-        }
-        if args.wait_for_ball {
-            robo.follow_line_loop(Some(catch_ball))?;
+    if let Some(mission) = &mission {
+        let mut mission = Mission::new(mission).expect("failed to load mission");
+        if from_flags && !args.all {
+            mission
+                .steps
+                .retain(|step| flag_actions.contains(&step.action.as_str()));
+            if args.stop_dist {
+                // not part of the competition, stops before the party like it always did
+                let party = mission
+                    .steps
+                    .iter()
+                    .position(|step| step.action == "party")
+                    .unwrap_or(mission.steps.len());
+                mission.steps.insert(
+                    party,
+                    mission::Step {
+                        name: None,
+                        action: "stop".to_string(),
+                        cond: "dist".into(),
+                        params: Default::default(),
+                    },
+                );
+            }
         }
-        if args.push_block {
-            robo.follow_line_loop(Some(push_block))?;
+        // build every step first to not fail in the middle of the course
+        let mut steps = Vec::new();
+        for step in &mission.steps {
+            let step_settings = settings::Settings::new(
+                [overrides.clone(), step.overrides()].concat(),
+                settings_file.clone(),
+            )
+            .unwrap_or_else(|e| panic!("invalid params in step `{}`: {}", step.name(), e));
+            let task = build_task(
+                &registry,
+                &step_settings,
+                step.name(),
+                &step.action,
+                &step.cond,
+            )?;
+            steps.push((task, step_settings));
         }
-        if args.throw_ball {
-            robo.follow_line_loop(Some(throw_ball))?;
+        for def in &mission.monitors {
+            robo.add_monitor(def.build(&registry)?);
         }
-        if args.stop_dist {
-            robo.follow_line_loop(Some(stop))?;
+        let start = start_step(
+            &args,
+            &mission
+                .steps
+                .iter()
+                .map(|step| (step.name().to_string(), step.action.clone()))
+                .collect::<Vec<_>>(),
+        )?;
+        for (task, step_settings) in steps.into_iter().skip(start) {
+            let settings = robo.set_settings(step_settings)?;
+            robo.follow_line_loop(Some(task))?;
+            robo.set_settings(settings)?;
         }
-        if args.party {
-            robo.follow_line_loop(Some(party))?;
+    } else if let Some(tree) = &args.tree {
+        BehaviourTree::load(tree, &registry)?.run(&mut robo)?;
+    } else if !args.task.is_empty() {
+        // build every task first to not fail in the middle of the course
+        let tasks = args
+            .task
+            .iter()
+            .map(|(action, cond)| {
+                let name = action.strip_prefix("act_").unwrap_or(action);
+                build_task(&registry, &robo.settings, name, action, &cond.as_str().into())
+            })
+            .collect::<Ev3Result<Vec<_>>>()?;
+        let start = start_step(
+            &args,
+            &tasks
+                .iter()
+                .zip(&args.task)
+                .map(|(task, (action, _))| (task.name.clone(), action.clone()))
+                .collect::<Vec<_>>(),
+        )?;
+        for task in tasks.into_iter().skip(start) {
+            robo.follow_line_loop(Some(task))?;
         }
+    } else if args.none {
+        robo.follow_line_loop(None)?;
+    } else if args.stop {
+        robo.steering.off()?;
+        robo.steering.wait_until_not_moving()?;
     };
+    robo.update_odometry()?;
+    let pose = robo.pose();
+    if let (Some(path), Some(recorder)) = (&args.record_course, &robo.course_recorder) {
+        recorder.save(path).expect("failed to save course profile");
+        println!("Course saved to {}", path.display());
+    }
     println!("Time elapsed: {:?}", started.elapsed().as_secs_f32());
+    println!(
+        "Pose: x: {:.1} cm, y: {:.1} cm, heading: {:.1}°, distance: {:.1} cm",
+        pose.x,
+        pose.y,
+        pose.heading_degrees(),
+        pose.distance
+    );
+    if !robo.splits.is_empty() {
+        let run = splits::Run::new(&robo.settings, std::mem::take(&mut robo.splits));
+        match &robo.settings.splits.history {
+            Some(history) => {
+                // the run is over, a missing history only costs the comparison
+                let runs = splits::load(history).unwrap_or_else(|e| {
+                    println!("failed to load split history {}: {}", history.display(), e);
+                    Vec::new()
+                });
+                splits::print(&run, splits::best(&runs, &run));
+                if let Err(e) = splits::save(history, &run) {
+                    println!("failed to save split history {}: {}", history.display(), e);
+                }
+            }
+            None => splits::print(&run, None),
+        }
+    }
     Ok(())
 }
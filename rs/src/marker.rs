@@ -0,0 +1,214 @@
+//! Decode the transitions of the middle sensor into stripe widths
+//! and match them against the named patterns in `settings.markers`.
+//!
+//! Everything is measured in travelled cm (odometry), so the widths do not change
+//! with the speed like the ms of `LineSensor::history` would.
+
+use crate::registry::Registry;
+use crate::robo::Robot;
+use crate::settings::{MarkerSettings, Pattern};
+use ev3dev_lang_rust::Ev3Result;
+
+/// transitions of the middle sensor, newest first: the new state and the travelled cm
+pub type History = [Option<(bool, f32)>; 16];
+
+/// add a transition to `history` if `on_line` changed
+pub fn record(history: &mut History, on_line: bool, travelled: f32) {
+    if history[0].map(|(state, _)| state) != Some(on_line) {
+        history.rotate_right(1);
+        history[0] = Some((on_line, travelled));
+    }
+}
+
+/// stripes crossed one after another, then no change for at least `markers.settle` cm
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    /// cm, oldest first
+    pub stripes: Vec<f32>,
+    /// travelled cm when the robot left the last stripe, identifies the marker
+    pub end: f32,
+}
+
+impl Pattern {
+    /// same number of stripes, each within `tolerance` (relative) of the pattern width
+    pub fn matches(&self, stripes: &[f32], tolerance: f32) -> bool {
+        self.stripes.len() == stripes.len()
+            && self
+                .stripes
+                .iter()
+                .zip(stripes)
+                .all(|(&expected, width)| (width - expected).abs() <= tolerance * expected)
+    }
+}
+
+/// the last complete marker in `history`, if the sensor has kept its state for at least
+/// `markers.settle` cm since, off the line or back on a solid one
+pub fn decode(history: &History, travelled: f32, settings: &MarkerSettings) -> Option<Marker> {
+    // nothing seen yet
+    let (on_line, changed) = history[0]?;
+    let since = travelled - changed;
+    if since < settings.settle || since > settings.max_age {
+        return None;
+    }
+    // back on the line: the newest transition starts the line, not a stripe
+    let stripes_from = if on_line { 1 } else { 0 };
+    let end = match history[stripes_from] {
+        Some((false, end)) => end,
+        _ => return None,
+    };
+    let mut stripes = Vec::new();
+    let mut newer_start: Option<f32> = None;
+    for pair in history[stripes_from..].chunks(2) {
+        let (off, on) = match pair {
+            [Some((false, off)), Some((true, on))] => (*off, *on),
+            _ => break,
+        };
+        if let Some(newer_start) = newer_start {
+            if newer_start - off > settings.gap {
+                break;
+            }
+        }
+        stripes.push(off - on);
+        newer_start = Some(on);
+    }
+    stripes.reverse();
+    Some(Marker { stripes, end })
+}
+
+/// name of the first pattern `marker` matches
+pub fn find<'a>(marker: &Marker, settings: &'a MarkerSettings) -> Option<&'a str> {
+    settings
+        .patterns
+        .iter()
+        .find(|(_, pattern)| pattern.matches(&marker.stripes, settings.tolerance))
+        .map(|(name, _)| name.as_str())
+}
+
+/// true once per marker if the last one matches `pattern`, or any pattern without one
+pub fn matches(robo: &mut Robot, pattern: Option<&str>) -> Ev3Result<bool> {
+    let travelled = robo.pose().distance;
+    let settings = &robo.settings.markers;
+    let marker = match decode(&robo.marker_history, travelled, settings) {
+        Some(marker) if robo.marker_used != Some(marker.end) => marker,
+        _ => return Ok(false),
+    };
+    let found = match pattern {
+        Some(pattern) => settings.patterns.get(pattern).is_some_and(|pattern| {
+            pattern.matches(&marker.stripes, settings.tolerance)
+        }),
+        None => find(&marker, settings).is_some(),
+    };
+    if found {
+        robo.marker_used = Some(marker.end);
+    }
+    Ok(found)
+}
+
+/// a `marker_<name>` condition for every pattern, the pattern itself is looked up when
+/// checking so mission steps can change it
+pub fn register(registry: &mut Registry, settings: &MarkerSettings) {
+    for name in settings.patterns.keys() {
+        let pattern = name.clone();
+        let added = registry.add_cond(
+            format!("marker_{}", name),
+            format!(
+                "the middle sensor crossed stripes matching markers.patterns.{}",
+                name
+            ),
+            std::sync::Arc::new(move |robo| matches(robo, Some(&pattern))),
+        );
+        if let Err(e) = added {
+            println!("skipping marker {}: {:?}", name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> MarkerSettings {
+        MarkerSettings {
+            tolerance: 0.3,
+            gap: 30.0,
+            settle: 15.0,
+            max_age: 50.0,
+            patterns: [
+                (
+                    "short".to_string(),
+                    Pattern {
+                        stripes: vec![5.0, 5.0, 5.0],
+                    },
+                ),
+                (
+                    "long".to_string(),
+                    Pattern {
+                        stripes: vec![15.0, 15.0],
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    /// history for stripes given as (start, end) in travelled cm, oldest first
+    fn history(stripes: &[(f32, f32)]) -> History {
+        let mut history = [None; 16];
+        for &(start, end) in stripes {
+            record(&mut history, true, start);
+            record(&mut history, false, end);
+        }
+        history
+    }
+
+    #[test]
+    fn three_short() {
+        let settings = settings();
+        let marker = decode(&history(&[(0.0, 5.0), (10.0, 15.0), (20.0, 25.0)]), 50.0, &settings)
+            .unwrap();
+        assert_eq!(marker.stripes, [5.0, 5.0, 5.0]);
+        assert_eq!(marker.end, 25.0);
+        assert_eq!(find(&marker, &settings), Some("short"));
+    }
+
+    #[test]
+    fn gap_ends_marker() {
+        let settings = settings();
+        // the first stripe is 50 cm before the others
+        let marker = decode(&history(&[(0.0, 15.0), (65.0, 80.0), (90.0, 95.0)]), 115.0, &settings);
+        assert_eq!(marker.unwrap().stripes, [15.0, 5.0]);
+    }
+
+    #[test]
+    fn not_settled() {
+        let settings = settings();
+        let history = history(&[(0.0, 5.0), (10.0, 15.0)]);
+        assert_eq!(decode(&history, 20.0, &settings), None);
+        assert!(decode(&history, 40.0, &settings).is_some());
+        // too old
+        assert_eq!(decode(&history, 70.0, &settings), None);
+    }
+
+    #[test]
+    fn dashes_then_line() {
+        let settings = settings();
+        let mut history = history(&[(0.0, 5.0), (10.0, 15.0), (20.0, 25.0)]);
+        record(&mut history, true, 30.0);
+        // the solid line after the dashes has not lasted long enough
+        assert_eq!(decode(&history, 40.0, &settings), None);
+        let marker = decode(&history, 60.0, &settings).unwrap();
+        assert_eq!(marker.stripes, [5.0, 5.0, 5.0]);
+        assert_eq!(marker.end, 25.0);
+        assert_eq!(decode(&history, 90.0, &settings), None);
+    }
+
+    #[test]
+    fn record_only_changes() {
+        let mut history = [None; 16];
+        record(&mut history, false, 1.0);
+        record(&mut history, false, 2.0);
+        record(&mut history, true, 3.0);
+        assert_eq!(&history[..3], [Some((true, 3.0)), Some((false, 1.0)), None]);
+    }
+}
@@ -0,0 +1,309 @@
+//! Menu on the brick display: edit settings, calibrate, choose the mission,
+//! show sensor values and start the run, for when there is no laptop at the track.
+//!
+//! up/down select, left/right change a value, centre opens, back returns.
+
+use crate::lcd::Lcd;
+use crate::mission::MISSIONS_DIR;
+use crate::selector::wait_for_release;
+use crate::settings::{Override, Settings, MENU_FILE};
+
+use ev3dev_lang_rust::sensors::{ColorSensor, LightSensor, Sensor, SensorPort, UltrasonicSensor};
+use ev3dev_lang_rust::{sound, Ev3Button, Ev3Result, PowerSupply};
+
+
+/// a setting that can be changed in the menu
+struct Item {
+    key: &'static str,
+    value: f32,
+    step: f32,
+    integer: bool,
+}
+
+impl Item {
+    fn new(key: &'static str, value: f32, step: f32, integer: bool) -> Self {
+        Self {
+            key,
+            value,
+            step,
+            integer,
+        }
+    }
+
+    fn value(&self) -> String {
+        if self.integer {
+            (self.value as i32).to_string()
+        } else {
+            format!("{:.1}", self.value)
+        }
+    }
+}
+
+/// what was chosen in the menu
+pub struct Choice {
+    /// the menu values as `--override` key=value pairs
+    pub overrides: Vec<Override>,
+    pub mission: Option<std::path::PathBuf>,
+}
+
+pub struct Menu {
+    lcd: Lcd,
+    button: Ev3Button,
+    items: Vec<Item>,
+    missions: Vec<std::path::PathBuf>,
+    mission: Option<usize>,
+}
+
+impl Menu {
+    pub fn new(settings: &Settings, mission: Option<std::path::PathBuf>) -> Ev3Result<Self> {
+        let mut missions = std::fs::read_dir(MISSIONS_DIR)
+            .map(|dir| {
+                dir.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "yaml"))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        missions.sort();
+        let mission = mission.map(|mission| {
+            match missions.iter().position(|path| *path == mission) {
+                Some(index) => index,
+                None => {
+                    missions.push(mission);
+                    missions.len() - 1
+                }
+            }
+        });
+        Ok(Self {
+            lcd: Lcd::new()?,
+            button: Ev3Button::new()?,
+            items: vec![
+                Item::new("steering.speed", settings.steering.speed as f32, 5.0, true),
+                Item::new(
+                    "steering.max_steering",
+                    settings.steering.max_steering as f32,
+                    5.0,
+                    true,
+                ),
+                Item::new(
+                    "sensors.left_threshold",
+                    settings.sensors.left_threshold as f32,
+                    1.0,
+                    true,
+                ),
+                Item::new(
+                    "sensors.middle_threshold",
+                    settings.sensors.middle_threshold as f32,
+                    1.0,
+                    true,
+                ),
+                Item::new(
+                    "sensors.right_threshold",
+                    settings.sensors.right_threshold as f32,
+                    1.0,
+                    true,
+                ),
+                Item::new("cond.dist", settings.cond.dist, 0.5, false),
+            ],
+            missions,
+            mission,
+        })
+    }
+
+    /// show the menu until "start" is chosen
+    pub fn run(mut self, calibrate: fn() -> Ev3Result<(i32, i32, i32)>) -> Ev3Result<Choice> {
+        let mut selected = 0;
+        loop {
+            let mission = match self.mission {
+                Some(index) => self.missions[index]
+                    .file_stem()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                None => "none".to_string(),
+            };
+            let entries = [
+                "start".to_string(),
+                "settings".to_string(),
+                format!("mission: {}", mission),
+                "calibrate".to_string(),
+                "sensors".to_string(),
+                "save".to_string(),
+            ];
+            self.draw("SDP2023", &entries, selected)?;
+            match self.wait_for_button() {
+                Key::Up => selected = (selected + entries.len() - 1) % entries.len(),
+                Key::Down => selected = (selected + 1) % entries.len(),
+                Key::Left => self.next_mission(false),
+                Key::Right => self.next_mission(true),
+                Key::Back => {}
+                Key::Enter => match selected {
+                    0 => break,
+                    1 => self.edit_settings()?,
+                    2 => self.next_mission(true),
+                    3 => {
+                        self.message(&[
+                            "calibrate:",
+                            "1. on the line",
+                            "2. off the line",
+                            "press a button",
+                            "after each beep",
+                        ])?;
+                        let (left, middle, right) = calibrate()?;
+                        self.items[2].value = left as f32;
+                        self.items[3].value = middle as f32;
+                        self.items[4].value = right as f32;
+                    }
+                    4 => self.show_sensors()?,
+                    _ => {
+                        self.save()?;
+                        self.message(&["saved to", "settings/menu.yaml"])?;
+                        sound::beep()?;
+                        std::thread::sleep(std::time::Duration::from_secs(1));
+                    }
+                },
+            }
+        }
+        self.lcd.clear();
+        self.lcd.flush()?;
+        Ok(Choice {
+            overrides: self
+                .items
+                .iter()
+                .map(|item| (item.key.to_string(), item.value()))
+                .collect(),
+            mission: self.mission.map(|index| self.missions[index].clone()),
+        })
+    }
+
+    fn edit_settings(&mut self) -> Ev3Result<()> {
+        let mut selected = 0;
+        loop {
+            let entries = self
+                .items
+                .iter()
+                .map(|item| format!("{} {}", item.key, item.value()))
+                .collect::<Vec<_>>();
+            self.draw("settings", &entries, selected)?;
+            let key = self.wait_for_button();
+            let item = &mut self.items[selected];
+            match key {
+                Key::Up => selected = (selected + entries.len() - 1) % entries.len(),
+                Key::Down => selected = (selected + 1) % entries.len(),
+                Key::Left => item.value -= item.step,
+                Key::Right => item.value += item.step,
+                Key::Enter | Key::Back => return Ok(()),
+            }
+        }
+    }
+
+    fn show_sensors(&mut self) -> Ev3Result<()> {
+        let left = LightSensor::get(SensorPort::In1)?;
+        let right = LightSensor::get(SensorPort::In4)?;
+        let middle = ColorSensor::get(SensorPort::In3)?;
+        let us = UltrasonicSensor::get(SensorPort::In2)?;
+        let psu = PowerSupply::new()?;
+        left.set_mode_reflect()?;
+        right.set_mode_reflect()?;
+        middle.set_mode(ColorSensor::MODE_COL_REFLECT)?;
+        loop {
+            self.button.process();
+            if !self.button.get_pressed_buttons().is_empty() {
+                wait_for_release(&self.button);
+                return Ok(());
+            }
+            self.message(&[
+                "sensors",
+                &format!("left: {}", left.get_value0()?),
+                &format!("middle: {}", middle.get_value0()?),
+                &format!("right: {}", right.get_value0()?),
+                &format!("us: {:.1} cm", us.get_distance_centimeters()?),
+                &format!("battery: {:.2} V", psu.get_voltage_now()? as f32 / 1000000.0),
+                "any button: back",
+            ])?;
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+
+    fn next_mission(&mut self, forward: bool) {
+        let count = self.missions.len();
+        if count == 0 {
+            return;
+        }
+        // cycle through "none" and all missions
+        self.mission = match (self.mission, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(count - 1),
+            (Some(index), true) if index + 1 < count => Some(index + 1),
+            (Some(index), false) if index > 0 => Some(index - 1),
+            (Some(_), _) => None,
+        };
+    }
+
+    /// write the menu values as a settings file, loaded before `--settings`
+    fn save(&self) -> Ev3Result<()> {
+        let mut yaml = String::new();
+        let mut section = "";
+        for item in &self.items {
+            let (prefix, key) = item.key.split_once('.').unwrap_or(("", item.key));
+            if prefix != section {
+                yaml.push_str(&format!("{}:\n", prefix));
+                section = prefix;
+            }
+            yaml.push_str(&format!("  {}: {}\n", key, item.value()));
+        }
+        std::fs::write(MENU_FILE, yaml)?;
+        Ok(())
+    }
+
+    /// title and a scrolling list with the selected entry inverted
+    fn draw(&mut self, title: &str, entries: &[String], selected: usize) -> Ev3Result<()> {
+        let visible = self.lcd.rows() - 1;
+        let first = selected.saturating_sub(visible - 1);
+        self.lcd.clear();
+        self.lcd.text(0, title, false);
+        for (row, entry) in entries.iter().enumerate().skip(first).take(visible) {
+            self.lcd.text(row - first + 1, entry, row == selected);
+        }
+        self.lcd.flush()
+    }
+
+    fn message(&mut self, lines: &[&str]) -> Ev3Result<()> {
+        self.lcd.clear();
+        for (row, line) in lines.iter().enumerate() {
+            self.lcd.text(row, line, false);
+        }
+        self.lcd.flush()
+    }
+
+    fn wait_for_button(&self) -> Key {
+        loop {
+            self.button.process();
+            let key = if self.button.is_up() {
+                Key::Up
+            } else if self.button.is_down() {
+                Key::Down
+            } else if self.button.is_left() {
+                Key::Left
+            } else if self.button.is_right() {
+                Key::Right
+            } else if self.button.is_enter() {
+                Key::Enter
+            } else if self.button.is_backspace() {
+                Key::Back
+            } else {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                continue;
+            };
+            wait_for_release(&self.button);
+            return key;
+        }
+    }
+}
+
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Back,
+}
@@ -0,0 +1,66 @@
+use crate::cond::CondDef;
+use crate::monitor::MonitorDef;
+use crate::settings::Override;
+use config::{Config, Map, Value, ValueKind};
+use serde_derive::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Step {
+    /// printed name, defaults to `action`
+    pub name: Option<String>,
+    pub action: String,
+    pub cond: CondDef,
+    /// settings for this step only, same layout as the settings file
+    #[serde(default)]
+    pub params: Map<String, Value>,
+}
+
+impl Step {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.action)
+    }
+
+    /// `params` as `--override` style key=value pairs
+    pub fn overrides(&self) -> Vec<Override> {
+        let mut overrides = Vec::new();
+        flatten("", &self.params, &mut overrides);
+        overrides
+    }
+}
+
+fn flatten(prefix: &str, table: &Map<String, Value>, overrides: &mut Vec<Override>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match &value.kind {
+            ValueKind::Table(table) => flatten(&key, table, overrides),
+            _ => overrides.push((key, value.to_string())),
+        }
+    }
+}
+
+pub const MISSIONS_DIR: &str = "/home/robot/SDP2022/rs/missions";
+
+/// the competition order, run by `--all` and the task flags
+pub const ALL: &str = "/home/robot/SDP2022/rs/missions/all.yaml";
+
+/// Ordered list of tasks, see `missions/all.yaml`
+#[derive(Debug, Deserialize)]
+pub struct Mission {
+    pub steps: Vec<Step>,
+    /// checked during every step
+    #[serde(default)]
+    pub monitors: Vec<MonitorDef>,
+}
+
+impl Mission {
+    pub fn new(file: &std::path::Path) -> Result<Self, config::ConfigError> {
+        Config::builder()
+            .add_source(config::File::with_name(file.to_str().unwrap_or("")))
+            .build()?
+            .try_deserialize()
+    }
+}
@@ -0,0 +1,125 @@
+//! Background checks that run every tick next to the active task, while following the line
+//! and while its action runs (`Robot::check_budget`),
+//! e.g. "stop if obstacle closer than 5 cm" or "log when the battery is low".
+
+use crate::events::Event;
+use crate::registry::Registry;
+use crate::robo::{Robot, TaskFunc};
+use ev3dev_lang_rust::{Ev3Error, Ev3Result};
+use serde_derive::Deserialize;
+
+/// what happens to the current task while a monitor's condition is true
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    /// only print it and run the action
+    #[default]
+    Annotate,
+    /// stop the motors until the condition is false again
+    Pause,
+    /// stop the motors and end the current task, its action does not run or is aborted
+    Preempt,
+}
+
+pub struct Monitor {
+    pub name: String,
+    pub cond: TaskFunc<bool>,
+    /// runs once each time the condition becomes true
+    pub act: Option<TaskFunc<()>>,
+    pub effect: Effect,
+    active: bool,
+}
+
+impl Monitor {
+    pub fn new(name: String, cond: TaskFunc<bool>, effect: Effect) -> Self {
+        Self {
+            name,
+            cond,
+            act: None,
+            effect,
+            active: false,
+        }
+    }
+
+    pub fn with_action(mut self, act: TaskFunc<()>) -> Self {
+        self.act = Some(act);
+        self
+    }
+}
+
+/// a monitor in a mission file, see `missions/all.yaml`
+#[derive(Debug, Deserialize)]
+pub struct MonitorDef {
+    /// printed name, defaults to `cond`
+    pub name: Option<String>,
+    pub cond: String,
+    pub action: Option<String>,
+    #[serde(default)]
+    pub effect: Effect,
+}
+
+impl MonitorDef {
+    pub fn build(&self, registry: &Registry) -> Ev3Result<Monitor> {
+        let cond = registry.get_cond(&self.cond).ok_or(Ev3Error::InternalError {
+            msg: format!("unknown condition `{}`, see --list-tasks", self.cond),
+        })?;
+        let name = self.name.clone().unwrap_or_else(|| self.cond.clone());
+        let monitor = Monitor::new(name, cond, self.effect);
+        Ok(match &self.action {
+            Some(action) => monitor.with_action(registry.get_action(action).ok_or(
+                Ev3Error::InternalError {
+                    msg: format!("unknown action `{}`, see --list-tasks", action),
+                },
+            )?),
+            None => monitor,
+        })
+    }
+}
+
+const PREEMPTED: &str = "preempted by a monitor";
+
+/// the error `Robot::check_budget` returns for `Effect::Preempt`
+pub fn preempt_error() -> Ev3Error {
+    Ev3Error::InternalError {
+        msg: PREEMPTED.to_string(),
+    }
+}
+
+pub fn is_preempt_error(e: &Ev3Error) -> bool {
+    matches!(e, Ev3Error::InternalError { msg } if msg == PREEMPTED)
+}
+
+/// evaluate all monitors of `robo` once, returns the strongest effect of the active ones
+pub fn check(robo: &mut Robot, task: Option<&str>) -> Ev3Result<Option<Effect>> {
+    // take them out to be able to pass the robot to the conditions
+    let mut monitors = std::mem::take(&mut robo.monitors);
+    let res = check_all(robo, &mut monitors, task);
+    monitors.append(&mut robo.monitors);
+    robo.monitors = monitors;
+    res
+}
+
+fn check_all(
+    robo: &mut Robot,
+    monitors: &mut [Monitor],
+    task: Option<&str>,
+) -> Ev3Result<Option<Effect>> {
+    let mut effect = None;
+    for monitor in monitors {
+        let active = (monitor.cond)(robo).unwrap_or(false);
+        if active && !monitor.active {
+            robo.events.publish(Event::MonitorTriggered {
+                name: monitor.name.clone(),
+                task: task.map(str::to_string),
+            });
+            if let Some(act) = &monitor.act {
+                act(robo)?;
+            }
+        }
+        monitor.active = active;
+        if active && Some(monitor.effect) > effect {
+            effect = Some(monitor.effect);
+        }
+    }
+    Ok(effect)
+}
@@ -0,0 +1,69 @@
+use ev3dev_lang_rust::motors::LargeMotor;
+use ev3dev_lang_rust::Ev3Result;
+use std::time::{Duration, Instant};
+
+/// Returned by the `MoveSteering` moves that end on their own
+/// (`on_for_seconds`, `on_for_rotations`, ...), to watch or stop them while they run.
+pub struct MotionHandle {
+    left_motor: LargeMotor,
+    right_motor: LargeMotor,
+    count_per_rot: i32,
+    start: (i32, i32),
+    /// end of a timed move
+    deadline: Option<Instant>,
+}
+
+impl MotionHandle {
+    pub fn new(
+        left_motor: LargeMotor,
+        right_motor: LargeMotor,
+        count_per_rot: i32,
+        duration: Option<Duration>,
+    ) -> Ev3Result<Self> {
+        let start = (left_motor.get_position()?, right_motor.get_position()?);
+        Ok(Self {
+            left_motor,
+            right_motor,
+            count_per_rot,
+            start,
+            deadline: duration.map(|duration| Instant::now() + duration),
+        })
+    }
+
+    pub fn is_done(&self) -> Ev3Result<bool> {
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Ok(true);
+            }
+        }
+        Ok(!self.left_motor.is_running()? && !self.right_motor.is_running()?)
+    }
+
+    /// wait until the move is done, returns false if `timeout` elapsed first
+    pub fn wait(&self, timeout: Option<Duration>) -> Ev3Result<bool> {
+        let started = Instant::now();
+        while !self.is_done()? {
+            if let Some(timeout) = timeout {
+                if started.elapsed() >= timeout {
+                    return Ok(false);
+                }
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        Ok(true)
+    }
+
+    /// stop both motors with their current stop action
+    pub fn cancel(&self) -> Ev3Result<()> {
+        self.left_motor.stop()?;
+        self.right_motor.stop()?;
+        Ok(())
+    }
+
+    /// rotations of the wheel that turned more since the move started
+    pub fn progress(&self) -> Ev3Result<f32> {
+        let left = (self.left_motor.get_position()? - self.start.0).abs();
+        let right = (self.right_motor.get_position()? - self.start.1).abs();
+        Ok(left.max(right) as f32 / self.count_per_rot as f32)
+    }
+}
@@ -1,9 +1,10 @@
 use ev3dev_lang_rust::motors::{LargeMotor};
 use ev3dev_lang_rust::Ev3Result;
+use crate::motion::MotionHandle;
 use clap;
 use serde_derive::Deserialize;
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug, Deserialize)]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Deserialize, PartialEq)]
 pub enum StopAction {
     COAST,
     BRAKE,
@@ -19,10 +20,87 @@ impl StopAction {
         }
     }
 }
+/// ms to go from 0 to `max_speed` and back, see ev3dev `ramp_up_sp`/`ramp_down_sp`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Ramp {
+    pub up: i32,
+    pub down: i32,
+}
+
+/// cm
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Geometry {
+    pub wheel_diameter: f32,
+    pub track_width: f32,
+}
+
+/// how `get_speed_steering` turns steering (-100..100) into wheel speeds
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SteeringModel {
+    /// inner wheel speed factor `(50 - |steering|) / 50`, steering above 100 makes
+    /// the inner wheel faster than the outer one
+    Legacy,
+    /// ev3dev2 `MoveSteering`: same factor as `Legacy`, but steering is limited to -100..100
+    Ev3dev2,
+    /// steering is proportional to the curvature (1/radius) of the path,
+    /// 100 = radius of half the track width (inner wheel stopped), so unlike the other
+    /// models ±100 is no turn on the spot, use `MoveSteering::spin` for that
+    Curvature,
+}
+
+impl SteeringModel {
+    /// `speed` in tacho counts per second, returns (left, right)
+    pub fn speeds(&self, steering: i32, speed: f32) -> (i32, i32) {
+        let (outer, inner) = match self {
+            SteeringModel::Legacy => (speed, speed * (50.0 - steering.abs() as f32) / 50.0),
+            SteeringModel::Ev3dev2 => {
+                let steering = steering.clamp(-100, 100);
+                (speed, speed * (50.0 - steering.abs() as f32) / 50.0)
+            }
+            SteeringModel::Curvature => {
+                // curvature times half the track width
+                let k = steering.clamp(-100, 100).abs() as f32 / 100.0;
+                (speed, speed * (1.0 - k) / (1.0 + k))
+            }
+        };
+        if steering >= 0 {
+            (outer as i32, inner as i32)
+        } else {
+            (inner as i32, outer as i32)
+        }
+    }
+}
+
+/// wheel speed for the tank methods, negative = backwards
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Speed {
+    /// of `max_speed`
+    Percent(f32),
+    DegPerSec(f32),
+    Rpm(f32),
+    /// uses the wheel diameter
+    CmPerSec(f32),
+}
+
 pub struct MoveSteering {
     pub left_motor: LargeMotor,
     pub right_motor: LargeMotor,
     pub max_speed: i32,
+    pub count_per_rot: i32,
+    pub geometry: Geometry,
+    pub model: SteeringModel,
+    pub stop_action: StopAction,
+    current_stop_action: std::cell::Cell<StopAction>,
+    pub ramp: Ramp,
+    current_ramp: std::cell::Cell<Ramp>,
+    /// deg/s correction per tacho count the wheels drifted apart while driving straight,
+    /// None = no synchronisation
+    pub sync_gain: Option<f32>,
+    /// tacho positions when the current straight drive started,
+    /// cleared by every move that is not a straight `on`
+    sync_start: std::cell::Cell<Option<(i32, i32)>>,
 }
 
 impl MoveSteering {
@@ -30,28 +108,77 @@ impl MoveSteering {
         left_motor: LargeMotor,
         right_motor: LargeMotor,
         stop_action: StopAction,
+        ramp: Ramp,
+        sync_gain: Option<f32>,
+        model: SteeringModel,
+        geometry: Geometry,
     ) -> Ev3Result<Self> {
         left_motor.set_stop_action(stop_action.to_str())?;
         right_motor.set_stop_action(stop_action.to_str())?;
+        for motor in [&left_motor, &right_motor] {
+            motor.set_ramp_up_sp(ramp.up)?;
+            motor.set_ramp_down_sp(ramp.down)?;
+        }
         left_motor.set_polarity(LargeMotor::POLARITY_NORMAL)?;
         right_motor.set_polarity(LargeMotor::POLARITY_NORMAL)?;
         let max_speed = left_motor.get_max_speed().unwrap_or(0);
+        let count_per_rot = left_motor.get_count_per_rot().unwrap_or(360);
 
         Ok(Self {
             left_motor,
             right_motor,
             max_speed,
+            count_per_rot,
+            geometry,
+            model,
+            stop_action,
+            current_stop_action: std::cell::Cell::new(stop_action),
+            ramp,
+            current_ramp: std::cell::Cell::new(ramp),
+            sync_gain,
+            sync_start: std::cell::Cell::new(None),
         })
     }
 
+    /// only writes the attributes if they changed
+    pub fn set_ramp(&self, ramp: Ramp) -> Ev3Result<()> {
+        if self.current_ramp.get() != ramp {
+            for motor in [&self.left_motor, &self.right_motor] {
+                motor.set_ramp_up_sp(ramp.up)?;
+                motor.set_ramp_down_sp(ramp.down)?;
+            }
+            self.current_ramp.set(ramp);
+        }
+        Ok(())
+    }
+
+    /// only writes the attribute if it changed
+    pub fn set_stop_action(&self, stop_action: StopAction) -> Ev3Result<()> {
+        if self.current_stop_action.get() != stop_action {
+            self.left_motor.set_stop_action(stop_action.to_str())?;
+            self.right_motor.set_stop_action(stop_action.to_str())?;
+            self.current_stop_action.set(stop_action);
+        }
+        Ok(())
+    }
+
+    /// call before starting a move so the handle knows the start positions
+    pub fn motion_handle(&self, duration: Option<std::time::Duration>) -> Ev3Result<MotionHandle> {
+        MotionHandle::new(
+            self.left_motor.clone(),
+            self.right_motor.clone(),
+            self.count_per_rot,
+            duration,
+        )
+    }
+
+    pub fn cm_to_counts(&self, cm: f32) -> i32 {
+        (cm / (std::f32::consts::PI * self.geometry.wheel_diameter) * self.count_per_rot as f32) as i32
+    }
+
     pub fn get_speed_steering(&self, steering: i32, speed: i32) -> (i32, i32) {
         let speed: f32 = speed as f32 / 100.0 * self.max_speed as f32;
-        let speed_factor = (50.0 - steering.abs() as f32) / 50.0;
-        if steering >= 0 {
-            (speed as i32, (speed * speed_factor) as i32)
-        } else {
-            ((speed * speed_factor) as i32, speed as i32)
-        }
+        self.model.speeds(steering, speed)
     }
 
     pub fn set_speed_sp(&self, speed: (i32, i32)) -> Ev3Result<()> {
@@ -73,6 +200,7 @@ impl MoveSteering {
     }
 
     pub fn off(&self) -> Ev3Result<()> {
+        self.sync_start.set(None);
         self.left_motor.stop()?;
         self.right_motor.stop()?;
         self.left_motor.wait_until_not_moving(None);
@@ -80,31 +208,144 @@ impl MoveSteering {
         Ok(())
     }
 
+    /// correct `speeds` so both wheels turned the same since the straight drive started
+    pub fn get_synced_speeds(&self, speeds: (i32, i32)) -> Ev3Result<(i32, i32)> {
+        let gain = match self.sync_gain {
+            Some(gain) if speeds.0 == speeds.1 => gain,
+            _ => {
+                self.sync_start.set(None);
+                return Ok(speeds);
+            }
+        };
+        let (left, right) = self.get_positions()?;
+        let (left_start, right_start) = match self.sync_start.get() {
+            Some(start) => start,
+            None => {
+                self.sync_start.set(Some((left, right)));
+                return Ok(speeds);
+            }
+        };
+        let direction = speeds.0.signum();
+        let error = ((left - left_start) - (right - right_start)) * direction;
+        let correction = (gain * error as f32) as i32 * direction;
+        Ok((
+            (speeds.0 - correction).clamp(-self.max_speed, self.max_speed),
+            (speeds.1 + correction).clamp(-self.max_speed, self.max_speed),
+        ))
+    }
+
     pub fn on(&self, steering: i32, speed: i32) -> Ev3Result<()> {
+        self.set_stop_action(self.stop_action)?;
         let speeds = self.get_speed_steering(steering, speed);
+        let speeds = self.get_synced_speeds(speeds)?;
         self.set_speed_sp(speeds)?;
         self.run_forever()?;
         Ok(())
     }
 
+    /// turn on the spot with `speed` percent, positive = right, the same in every `SteeringModel`
+    pub fn spin(&self, speed: i32) -> Ev3Result<()> {
+        self.sync_start.set(None);
+        self.set_stop_action(self.stop_action)?;
+        let speed = (speed as f32 / 100.0 * self.max_speed as f32) as i32;
+        self.set_speed_sp((speed, -speed))?;
+        self.run_forever()?;
+        Ok(())
+    }
+
     pub fn on_for_seconds(
         &self,
         steering: i32,
         speed: i32,
-        seconds: i32,
+        seconds: f32,
         block: bool,
-    ) -> Ev3Result<()> {
+    ) -> Ev3Result<MotionHandle> {
+        self.sync_start.set(None);
+        self.set_stop_action(self.stop_action)?;
         let speeds = self.get_speed_steering(steering, speed);
-        let duration = std::time::Duration::from_secs(seconds as u64);
+        let duration = std::time::Duration::from_secs_f32(seconds.max(0.0));
+        let handle = self.motion_handle(Some(duration))?;
         self.set_speed_sp(speeds)?;
         self.run_timed(duration)?;
         if block {
             std::thread::sleep(duration);
             self.off()?;
         }
+        Ok(handle)
+    }
+
+    /// `speed_sp` value (tacho counts per second) for `speed`
+    pub fn to_speed_sp(&self, speed: Speed) -> i32 {
+        let counts_per_degree = self.count_per_rot as f32 / 360.0;
+        let speed_sp = match speed {
+            Speed::Percent(percent) => percent / 100.0 * self.max_speed as f32,
+            Speed::DegPerSec(degrees) => degrees * counts_per_degree,
+            Speed::Rpm(rpm) => rpm * 6.0 * counts_per_degree,
+            Speed::CmPerSec(cm) => {
+                cm / (std::f32::consts::PI * self.geometry.wheel_diameter) * self.count_per_rot as f32
+            }
+        };
+        (speed_sp as i32).clamp(-self.max_speed, self.max_speed)
+    }
+
+    /// like ev3dev2 `MoveTank.on`
+    pub fn tank_on(&self, left: Speed, right: Speed) -> Ev3Result<()> {
+        self.sync_start.set(None);
+        self.set_stop_action(self.stop_action)?;
+        self.set_speed_sp((self.to_speed_sp(left), self.to_speed_sp(right)))?;
+        self.run_forever()?;
         Ok(())
     }
 
+    pub fn tank_on_for_seconds(
+        &self,
+        left: Speed,
+        right: Speed,
+        seconds: f32,
+        block: bool,
+    ) -> Ev3Result<MotionHandle> {
+        self.sync_start.set(None);
+        self.set_stop_action(self.stop_action)?;
+        let duration = std::time::Duration::from_secs_f32(seconds.max(0.0));
+        let handle = self.motion_handle(Some(duration))?;
+        self.set_speed_sp((self.to_speed_sp(left), self.to_speed_sp(right)))?;
+        self.run_timed(duration)?;
+        if block {
+            std::thread::sleep(duration);
+            self.off()?;
+        }
+        Ok(handle)
+    }
+
+    /// the faster wheel turns `rotations`, the other one proportionally less
+    pub fn tank_on_for_rotations(
+        &self,
+        left: Speed,
+        right: Speed,
+        rotations: f32,
+        block: bool,
+    ) -> Ev3Result<MotionHandle> {
+        let handle = self.motion_handle(None)?;
+        self.sync_start.set(None);
+        self.set_stop_action(self.stop_action)?;
+        let speeds = (self.to_speed_sp(left), self.to_speed_sp(right));
+        let fastest = speeds.0.abs().max(speeds.1.abs());
+        if fastest == 0 {
+            return Ok(handle);
+        }
+        let counts = rotations * self.count_per_rot as f32;
+        let left_delta = (counts * speeds.0 as f32 / fastest as f32) as i32;
+        let right_delta = (counts * speeds.1 as f32 / fastest as f32) as i32;
+        self.set_speed_sp((speeds.0.abs(), speeds.1.abs()))?;
+        self.left_motor.run_to_rel_pos(Some(left_delta))?;
+        self.right_motor.run_to_rel_pos(Some(right_delta))?;
+        if block {
+            self.wait_until_not_moving()?;
+            self.off()?;
+        }
+        Ok(handle)
+    }
+
     pub fn get_rel_pos_and_speed(&self, speed: i32, mut degrees: f32) -> (i32, i32) {
         if speed < 0 {
             degrees *= -1.0;
@@ -120,7 +361,10 @@ impl MoveSteering {
         speed: i32,
         rotations: f32,
         block: bool,
-    ) -> Ev3Result<()> {
+    ) -> Ev3Result<MotionHandle> {
+        let handle = self.motion_handle(None)?;
+        self.sync_start.set(None);
+        self.set_stop_action(self.stop_action)?;
         let speeds = self.get_speed_steering(steering, speed);
         let degrees = rotations * 360.0;
         let (left_degrees, right_degrees) =
@@ -141,7 +385,91 @@ impl MoveSteering {
             self.right_motor.wait_until_not_moving(None);
             self.off()?;
         }
-        Ok(())
+        Ok(handle)
+    }
+
+    /// drive the left and right wheel `left`/`right` cm, the wheel with the longer way
+    /// runs at `speed` percent, a negative speed drives backwards
+    pub fn on_for_distances(
+        &self,
+        left: f32,
+        right: f32,
+        speed: i32,
+        stop_action: Option<StopAction>,
+        block: bool,
+    ) -> Ev3Result<MotionHandle> {
+        let handle = self.motion_handle(None)?;
+        self.sync_start.set(None);
+        let longest = left.abs().max(right.abs());
+        if longest == 0.0 || speed == 0 {
+            return Ok(handle);
+        }
+        self.set_stop_action(stop_action.unwrap_or(self.stop_action))?;
+        let speed_sp = speed.abs() as f32 / 100.0 * self.max_speed as f32;
+        self.set_speed_sp((
+            (speed_sp * left.abs() / longest) as i32,
+            (speed_sp * right.abs() / longest) as i32,
+        ))?;
+        let direction = speed.signum();
+        self.left_motor
+            .run_to_rel_pos(Some(direction * self.cm_to_counts(left)))?;
+        self.right_motor
+            .run_to_rel_pos(Some(direction * self.cm_to_counts(right)))?;
+        if block {
+            self.wait_until_not_moving()?;
+            self.off()?;
+        }
+        Ok(handle)
+    }
+
+    /// turn on the spot, positive = right
+    pub fn turn_degrees(
+        &self,
+        angle: f32,
+        speed: i32,
+        stop_action: Option<StopAction>,
+        block: bool,
+    ) -> Ev3Result<MotionHandle> {
+        let wheel = std::f32::consts::PI * self.geometry.track_width * angle / 360.0;
+        self.on_for_distances(wheel, -wheel, speed, stop_action, block)
+    }
+
+    /// drive straight, negative distance = backwards
+    pub fn drive_cm(
+        &self,
+        distance: f32,
+        speed: i32,
+        stop_action: Option<StopAction>,
+        block: bool,
+    ) -> Ev3Result<MotionHandle> {
+        self.on_for_distances(distance, distance, speed, stop_action, block)
+    }
+
+    /// drive `angle` degrees along a circle with `radius_cm` (measured to the middle
+    /// between the wheels), positive angle = right
+    pub fn arc(
+        &self,
+        radius_cm: f32,
+        angle: f32,
+        speed: i32,
+        stop_action: Option<StopAction>,
+        block: bool,
+    ) -> Ev3Result<MotionHandle> {
+        let radians = angle.abs().to_radians();
+        let outer = (radius_cm + self.geometry.track_width / 2.0) * radians;
+        let inner = (radius_cm - self.geometry.track_width / 2.0) * radians;
+        if angle >= 0.0 {
+            self.on_for_distances(outer, inner, speed, stop_action, block)
+        } else {
+            self.on_for_distances(inner, outer, speed, stop_action, block)
+        }
+    }
+
+    pub fn get_positions(&self) -> Ev3Result<(i32, i32)> {
+        Ok((
+            self.left_motor.get_position()?,
+            self.right_motor.get_position()?,
+        ))
     }
 
     pub fn wait_until_not_moving(&self) -> Ev3Result<()> {
@@ -149,4 +477,52 @@ impl MoveSteering {
         self.right_motor.wait_until_not_moving(None);
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SteeringModel;
+
+    fn speeds(model: SteeringModel, steering: i32) -> (i32, i32) {
+        model.speeds(steering, 1000.0)
+    }
+
+    #[test]
+    fn legacy() {
+        assert_eq!(speeds(SteeringModel::Legacy, 0), (1000, 1000));
+        assert_eq!(speeds(SteeringModel::Legacy, 25), (1000, 500));
+        assert_eq!(speeds(SteeringModel::Legacy, -25), (500, 1000));
+        assert_eq!(speeds(SteeringModel::Legacy, 50), (1000, 0));
+        assert_eq!(speeds(SteeringModel::Legacy, 60), (1000, -200));
+        assert_eq!(speeds(SteeringModel::Legacy, 100), (1000, -1000));
+        assert_eq!(speeds(SteeringModel::Legacy, -100), (-1000, 1000));
+        assert_eq!(speeds(SteeringModel::Legacy, 150), (1000, -2000));
+    }
+
+    #[test]
+    fn ev3dev2() {
+        assert_eq!(speeds(SteeringModel::Ev3dev2, 0), (1000, 1000));
+        assert_eq!(speeds(SteeringModel::Ev3dev2, 25), (1000, 500));
+        assert_eq!(speeds(SteeringModel::Ev3dev2, -50), (0, 1000));
+        assert_eq!(speeds(SteeringModel::Ev3dev2, 60), (1000, -200));
+        assert_eq!(speeds(SteeringModel::Ev3dev2, 100), (1000, -1000));
+        assert_eq!(speeds(SteeringModel::Ev3dev2, 150), (1000, -1000));
+        assert_eq!(speeds(SteeringModel::Ev3dev2, -150), (-1000, 1000));
+    }
+
+    #[test]
+    fn curvature() {
+        assert_eq!(speeds(SteeringModel::Curvature, 0), (1000, 1000));
+        // radius = track width: inner wheel at (r - b/2) / (r + b/2)
+        assert_eq!(speeds(SteeringModel::Curvature, 50), (1000, 333));
+        assert_eq!(speeds(SteeringModel::Curvature, -50), (333, 1000));
+        assert_eq!(speeds(SteeringModel::Curvature, 100), (1000, 0));
+        assert_eq!(speeds(SteeringModel::Curvature, 150), (1000, 0));
+    }
+
+    #[test]
+    fn backwards() {
+        assert_eq!(SteeringModel::Legacy.speeds(25, -1000.0), (-1000, -500));
+        assert_eq!(SteeringModel::Curvature.speeds(-50, -1000.0), (-333, -1000));
+    }
+}
@@ -0,0 +1,280 @@
+//! Tell walls, barriers and blocks apart from the ultrasonic distance profile while
+//! approaching them (approach rate, echo stability) and a small sweep for the width.
+
+use crate::events::Event;
+use crate::robo::Robot;
+use crate::settings::ObstacleSettings;
+use ev3dev_lang_rust::Ev3Result;
+use serde_derive::Deserialize;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ObstacleClass {
+    /// wide and flat
+    Wall,
+    /// thin bars, unstable echo
+    Barrier,
+    /// narrow with a stable echo
+    Block,
+    Unknown,
+}
+
+/// Distance readings over the travelled distance, filled by `follow_line_once`.
+#[derive(Debug, Default)]
+pub struct ObstacleProfile {
+    /// (travelled cm, ultrasonic cm)
+    samples: VecDeque<(f32, f32)>,
+    /// result of the last sweep and where it was, valid for `window` cm
+    pub last: Option<(ObstacleClass, f32)>,
+}
+
+impl ObstacleProfile {
+    pub fn sample(&mut self, travelled: f32, distance: f32, settings: &ObstacleSettings) {
+        if let Some((_, at)) = self.last {
+            if travelled - at > settings.window {
+                // probably another obstacle by now
+                self.last = None;
+            }
+        }
+        self.samples.push_back((travelled, distance));
+        while let Some(&(first, _)) = self.samples.front() {
+            if travelled - first <= settings.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// least squares fit of distance over travelled distance, returns (slope, residual std dev)
+    pub fn fit(&self, settings: &ObstacleSettings) -> Option<(f32, f32)> {
+        let samples: Vec<&(f32, f32)> = self
+            .samples
+            .iter()
+            .filter(|(_, distance)| *distance <= settings.max_range)
+            .collect();
+        if samples.len() < settings.min_samples {
+            return None;
+        }
+        let n = samples.len() as f32;
+        let mean_x = samples.iter().map(|(x, _)| x).sum::<f32>() / n;
+        let mean_y = samples.iter().map(|(_, y)| y).sum::<f32>() / n;
+        let var_x = samples
+            .iter()
+            .map(|(x, _)| (x - mean_x).powi(2))
+            .sum::<f32>();
+        if var_x < f32::EPSILON {
+            // standing still
+            return None;
+        }
+        let slope = samples
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum::<f32>()
+            / var_x;
+        let residual = (samples
+            .iter()
+            .map(|(x, y)| (y - (mean_y + slope * (x - mean_x))).powi(2))
+            .sum::<f32>()
+            / n)
+            .sqrt();
+        Some((slope, residual))
+    }
+
+    /// share of readings without an echo, barriers lose it often
+    pub fn dropouts(&self, settings: &ObstacleSettings) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.samples
+            .iter()
+            .filter(|(_, distance)| *distance > settings.max_range)
+            .count() as f32
+            / self.samples.len() as f32
+    }
+}
+
+/// cm covered at `center` cm by a flat obstacle seen up to `extent` degrees to each side
+pub fn width(center: f32, extent: [f32; 2]) -> f32 {
+    center * (extent[0].to_radians().tan() + extent[1].to_radians().tan())
+}
+
+/// the widest obstacle a sweep at `center` cm can see, wider ones are measured this wide
+pub fn max_width(center: f32, settings: &ObstacleSettings) -> f32 {
+    width(center, [settings.sweep_angle; 2])
+}
+
+/// turn to both sides by up to `sweep_angle` and return the width (cm) of what is seen in front
+pub fn sweep(robo: &mut Robot) -> Ev3Result<f32> {
+    let settings = &robo.settings.obstacle;
+    let (angle, speed, steps) = (
+        settings.sweep_angle,
+        settings.sweep_speed,
+        settings.sweep_steps,
+    );
+    let tolerance = settings.width_tolerance;
+    let center = robo.us_sensor.get_distance_centimeters()?;
+    let step = angle / steps as f32;
+    let mut extent = [0.0f32; 2];
+    for (side, sign) in [(0, -1.0f32), (1, 1.0)] {
+        let mut turned = 0.0;
+        for _ in 0..steps {
+            robo.turn_degrees(sign * step, speed)?;
+            turned += step;
+            let distance = robo.us_sensor.get_distance_centimeters()?;
+            // a flat obstacle is further away at an angle
+            let expected = center / turned.to_radians().cos();
+            if (distance - expected).abs() > tolerance * expected {
+                break;
+            }
+            extent[side] = turned;
+        }
+        robo.turn_degrees(-sign * turned, speed)?;
+    }
+    Ok(width(center, extent))
+}
+
+/// classify what is in front, without `sweep_width` the width is unknown,
+/// with it the robot stops and turns to both sides
+pub fn classify(robo: &mut Robot, sweep_width: bool) -> Ev3Result<ObstacleClass> {
+    let fit = robo.obstacle.fit(&robo.settings.obstacle);
+    let dropouts = robo.obstacle.dropouts(&robo.settings.obstacle);
+    let width = if sweep_width {
+        robo.steering.off()?;
+        Some(sweep(robo)?)
+    } else {
+        None
+    };
+    let settings = &robo.settings.obstacle;
+    let class = match fit {
+        // not approaching something that stands still
+        Some((slope, _)) if slope.abs() < settings.approach_rate => ObstacleClass::Unknown,
+        Some((_, residual)) if residual > settings.stability || dropouts > settings.dropouts => {
+            ObstacleClass::Barrier
+        }
+        _ => match width {
+            Some(width) if width >= settings.wall_width => ObstacleClass::Wall,
+            Some(width) if width <= settings.block_width => ObstacleClass::Block,
+            _ => ObstacleClass::Unknown,
+        },
+    };
+    robo.events.publish(Event::ObstacleClassified {
+        class,
+        distance: robo.us_sensor.get_distance_centimeters()?,
+        width,
+    });
+    Ok(class)
+}
+
+/// true if something closer than `cond.dist` is of `class`, sweeps once per obstacle,
+/// so the robot stops and turns in the middle of following the line
+pub fn is_class(robo: &mut Robot, class: ObstacleClass) -> Ev3Result<bool> {
+    if robo.us_sensor.get_distance_centimeters()? >= robo.settings.cond.dist {
+        return Ok(false);
+    }
+    let found = match robo.obstacle.last {
+        Some((found, _)) => found,
+        None => {
+            let found = classify(robo, true)?;
+            robo.obstacle.last = Some((found, robo.pose().distance));
+            found
+        }
+    };
+    Ok(found == class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ObstacleSettings {
+        ObstacleSettings {
+            window: 30.0,
+            min_samples: 5,
+            max_range: 100.0,
+            approach_rate: 0.5,
+            stability: 2.0,
+            dropouts: 0.3,
+            sweep_angle: 45.0,
+            sweep_speed: 20,
+            sweep_steps: 3,
+            width_tolerance: 0.25,
+            wall_width: 25.0,
+            block_width: 12.0,
+        }
+    }
+
+    fn profile(samples: &[(f32, f32)]) -> ObstacleProfile {
+        let mut profile = ObstacleProfile::default();
+        for &(travelled, distance) in samples {
+            profile.sample(travelled, distance, &settings());
+        }
+        profile
+    }
+
+    #[test]
+    fn fit_approach() {
+        // 1 cm closer per cm travelled, the reading without echo is left out
+        let (slope, residual) = profile(&[
+            (0.0, 50.0),
+            (1.0, 49.0),
+            (2.0, 255.0),
+            (3.0, 47.0),
+            (4.0, 46.0),
+            (5.0, 45.0),
+        ])
+        .fit(&settings())
+        .unwrap();
+        assert!((slope + 1.0).abs() < 1e-4);
+        assert!(residual < 1e-4);
+    }
+
+    #[test]
+    fn fit_needs_samples_and_movement() {
+        assert_eq!(profile(&[(0.0, 50.0), (1.0, 49.0)]).fit(&settings()), None);
+        assert_eq!(profile(&[(3.0, 50.0); 6]).fit(&settings()), None);
+    }
+
+    #[test]
+    fn fit_window() {
+        // the samples more than `window` cm back are dropped
+        let mut samples: Vec<(f32, f32)> = (0..5).map(|i| (i as f32, 200.0)).collect();
+        samples.extend((0..6).map(|i| (40.0 + i as f32, 50.0 - i as f32)));
+        let profile = profile(&samples);
+        assert_eq!(profile.dropouts(&settings()), 0.0);
+        let (slope, _) = profile.fit(&settings()).unwrap();
+        assert!((slope + 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn dropouts() {
+        assert_eq!(ObstacleProfile::default().dropouts(&settings()), 0.0);
+        let profile = profile(&[(0.0, 50.0), (1.0, 255.0), (2.0, 48.0), (3.0, 255.0)]);
+        assert_eq!(profile.dropouts(&settings()), 0.5);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(width(20.0, [0.0, 0.0]), 0.0);
+        assert!((width(10.0, [45.0, 0.0]) - 10.0).abs() < 1e-4);
+        assert!((width(10.0, [45.0, 45.0]) - 20.0).abs() < 1e-4);
+        assert!((max_width(15.0, &settings()) - 30.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn default_wall_is_reachable() {
+        // the conditions sweep once something is closer than `cond.dist`
+        let settings: crate::settings::Settings = config::Config::builder()
+            .add_source(config::File::from_str(
+                include_str!("../settings/default.yaml"),
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let obstacle = &settings.obstacle;
+        assert!(max_width(settings.cond.dist * 0.9, obstacle) >= obstacle.wall_width);
+        assert!(obstacle.block_width < obstacle.wall_width);
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pose {
+    /// cm
+    pub x: f32,
+    /// cm
+    pub y: f32,
+    /// rad, counter clockwise, 0 = start direction
+    pub heading: f32,
+    /// cm driven in total (mean of both wheels)
+    pub distance: f32,
+}
+
+impl Pose {
+    pub fn heading_degrees(&self) -> f32 {
+        self.heading.to_degrees()
+    }
+}
+
+/// Integrates the tacho counts of the two drive motors into a `Pose`.
+pub struct Odometry {
+    wheel_diameter: f32,
+    track_width: f32,
+    last: Option<(i32, i32)>,
+    pose: Arc<Mutex<Pose>>,
+}
+
+impl Odometry {
+    pub fn new(wheel_diameter: f32, track_width: f32) -> Self {
+        Self {
+            wheel_diameter,
+            track_width,
+            last: None,
+            pose: Arc::new(Mutex::new(Pose::default())),
+        }
+    }
+
+    pub fn counts_to_cm(&self, counts: i32, count_per_rot: i32) -> f32 {
+        counts as f32 / count_per_rot as f32 * std::f32::consts::PI * self.wheel_diameter
+    }
+
+    /// `left`/`right` are the absolute tacho positions of the drive motors
+    pub fn update(&mut self, left: i32, right: i32, count_per_rot: i32) -> Pose {
+        let (last_left, last_right) = self.last.unwrap_or((left, right));
+        self.last = Some((left, right));
+        let dl = self.counts_to_cm(left - last_left, count_per_rot);
+        let dr = self.counts_to_cm(right - last_right, count_per_rot);
+        let ds = (dl + dr) / 2.0;
+        let dtheta = (dr - dl) / self.track_width;
+
+        let mut pose = self.pose.lock().unwrap();
+        let theta = pose.heading;
+        if dtheta.abs() < 1e-6 {
+            pose.x += ds * theta.cos();
+            pose.y += ds * theta.sin();
+        } else {
+            // exact for a constant curvature between two updates
+            let radius = ds / dtheta;
+            pose.x += radius * ((theta + dtheta).sin() - theta.sin());
+            pose.y -= radius * ((theta + dtheta).cos() - theta.cos());
+        }
+        pose.heading = theta + dtheta;
+        pose.distance += ds;
+        *pose
+    }
+
+    pub fn reset(&mut self) {
+        self.last = None;
+        *self.pose.lock().unwrap() = Pose::default();
+    }
+
+    pub fn pose(&self) -> Pose {
+        *self.pose.lock().unwrap()
+    }
+
+    /// handle for other threads (e.g. mqtt) to read the current pose
+    pub fn shared_pose(&self) -> Arc<Mutex<Pose>> {
+        self.pose.clone()
+    }
+}
@@ -0,0 +1,247 @@
+use crate::events::Event;
+use crate::motion::MotionHandle;
+use crate::robo::Robot;
+use crate::settings::PushBlockTimeouts;
+use ev3dev_lang_rust::Ev3Result;
+use std::time::{Duration, Instant};
+
+/// `machine` of the state events
+const MACHINE: &str = "push block";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// turn 90° to the right and drive until the middle sensor finds the line to the block
+    LeaveLine,
+    /// follow the line until the block is in front of the robot
+    ApproachBlock,
+    /// follow the line until the block is gone
+    Push,
+    /// turn around and search the line
+    UTurn,
+    /// follow the line back until it ends
+    ReturnAlongLine,
+    /// drive back onto the main line
+    RejoinLine,
+    Done,
+}
+
+impl State {
+    pub fn timeout(&self, timeouts: &PushBlockTimeouts) -> Option<Duration> {
+        let ms = match self {
+            State::LeaveLine => timeouts.leave_line,
+            State::ApproachBlock => timeouts.approach_block,
+            State::Push => timeouts.push,
+            State::UTurn => timeouts.u_turn,
+            State::ReturnAlongLine => timeouts.return_along_line,
+            State::RejoinLine => timeouts.rejoin_line,
+            State::Done => return None,
+        };
+        Some(Duration::from_millis(ms))
+    }
+
+    /// where to continue when the state timed out
+    pub fn fallback(&self) -> State {
+        match self {
+            State::LeaveLine => State::ApproachBlock,
+            // no block in sight, go back without pushing
+            State::ApproachBlock => State::UTurn,
+            State::Push => State::UTurn,
+            State::UTurn => State::ReturnAlongLine,
+            State::ReturnAlongLine => State::RejoinLine,
+            State::RejoinLine | State::Done => State::Done,
+        }
+    }
+}
+
+pub struct PushBlockMachine {
+    pub state: State,
+    entered: Instant,
+    /// between `enter` and `exit` of `state`
+    active: bool,
+    handle: Option<MotionHandle>,
+}
+
+impl PushBlockMachine {
+    pub fn new() -> Self {
+        Self {
+            state: State::LeaveLine,
+            entered: Instant::now(),
+            active: false,
+            handle: None,
+        }
+    }
+
+    pub fn run(&mut self, robo: &mut Robot) -> Ev3Result<()> {
+        let res = self.run_states(robo);
+        // also when a state failed, e.g. with the budget or monitor error of `follow_line_once`,
+        // that error is returned since `run_task` decides on it (fallback, preempt)
+        if res.is_err() && self.active {
+            let _ = self.exit(robo);
+        }
+        robo.set_state("");
+        res
+    }
+
+    fn run_states(&mut self, robo: &mut Robot) -> Ev3Result<()> {
+        self.enter(robo)?;
+        while self.state != State::Done {
+            robo.check_budget()?;
+            let timed_out = self
+                .state
+                .timeout(&robo.settings.act.push_block.timeouts)
+                .is_some_and(|timeout| self.entered.elapsed() > timeout);
+            let next = if timed_out {
+                robo.events.publish(Event::StateTimedOut {
+                    machine: MACHINE,
+                    state: format!("{:?}", self.state),
+                });
+                Some(self.state.fallback())
+            } else {
+                self.update(robo)?
+            };
+            if let Some(next) = next {
+                self.exit(robo)?;
+                self.state = next;
+                self.enter(robo)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, robo: &mut Robot) -> Ev3Result<()> {
+        self.active = true;
+        robo.events.publish(Event::StateEntered {
+            machine: MACHINE,
+            state: format!("{:?}", self.state),
+        });
+        robo.set_state(&format!("push_block/{:?}", self.state));
+        let settings = &robo.settings.act.push_block;
+        match self.state {
+            State::LeaveLine => {
+                let leave_line = &settings.leave_line;
+                let (steering, speed, rotations, angle, drive) = (
+                    leave_line.on_for_rotations.steering,
+                    leave_line.on_for_rotations.speed,
+                    leave_line.on_for_rotations.rotations,
+                    leave_line.angle,
+                    (leave_line.drive.steering, leave_line.drive.speed),
+                );
+                robo.with_ramp(leave_line.ramp, |robo| {
+                    robo.turn(steering, speed, rotations, angle)
+                })?;
+                robo.steering.on(drive.0, drive.1)?;
+            }
+            State::UTurn => {
+                let u_turn = &settings.u_turn;
+                let (steering, speed, rotations, angle, drive) = (
+                    u_turn.on_for_rotations.steering,
+                    u_turn.on_for_rotations.speed,
+                    u_turn.on_for_rotations.rotations,
+                    u_turn.angle,
+                    (u_turn.drive.steering, u_turn.drive.speed),
+                );
+                robo.with_ramp(u_turn.ramp, |robo| {
+                    robo.turn(steering, speed, rotations, angle)
+                })?;
+                robo.steering.on(drive.0, drive.1)?;
+            }
+            State::RejoinLine => {
+                let return_to_line = &settings.return_to_line;
+                // the move keeps running after `enter`, `exit` restores `steering.ramp`
+                if let Some(ramp) = return_to_line.ramp {
+                    robo.steering.set_ramp(ramp)?;
+                }
+                self.handle = Some(match (return_to_line.radius, return_to_line.angle) {
+                    (Some(radius), Some(angle)) => robo.steering.arc(
+                        radius,
+                        return_to_line.steering.signum() as f32 * angle,
+                        return_to_line.speed,
+                        None,
+                        false,
+                    )?,
+                    _ => robo.steering.on_for_seconds(
+                        return_to_line.steering,
+                        return_to_line.speed,
+                        return_to_line.seconds,
+                        false,
+                    )?,
+                });
+            }
+            State::ApproachBlock | State::Push | State::ReturnAlongLine | State::Done => {}
+        }
+        self.entered = Instant::now();
+        Ok(())
+    }
+
+    fn exit(&mut self, robo: &mut Robot) -> Ev3Result<()> {
+        self.active = false;
+        robo.events.publish(Event::StateExited {
+            machine: MACHINE,
+            state: format!("{:?}", self.state),
+            duration: self.entered.elapsed(),
+        });
+        if let Some(handle) = self.handle.take() {
+            handle.cancel()?;
+        }
+        match self.state {
+            State::LeaveLine => robo.steering.off()?,
+            State::RejoinLine => {
+                robo.steering.off()?;
+                robo.steering.set_ramp(robo.steering.ramp)?;
+            }
+            State::Push => {
+                robo.steering.off()?;
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// one tick, returns the next state if the current one is finished
+    fn update(&mut self, robo: &mut Robot) -> Ev3Result<Option<State>> {
+        let drive_until_dist = robo.settings.act.push_block.drive_until_dist;
+        Ok(match self.state {
+            State::LeaveLine => robo.middle_sensor.on_line().then_some(State::ApproachBlock),
+            State::UTurn => robo.middle_sensor.on_line().then_some(State::ReturnAlongLine),
+            State::ApproachBlock => {
+                if robo.us_sensor.get_distance_centimeters()? <= drive_until_dist {
+                    Some(State::Push)
+                } else {
+                    robo.follow_line_once()?;
+                    None
+                }
+            }
+            State::Push => {
+                if robo.us_sensor.get_distance_centimeters()? > drive_until_dist {
+                    Some(State::UTurn)
+                } else {
+                    robo.follow_line_once()?;
+                    None
+                }
+            }
+            State::ReturnAlongLine => {
+                if robo.left_sensor.on_line()
+                    || robo.middle_sensor.on_line()
+                    || robo.right_sensor.on_line()
+                {
+                    robo.follow_line_once()?;
+                    None
+                } else {
+                    Some(State::RejoinLine)
+                }
+            }
+            State::RejoinLine => match &self.handle {
+                Some(handle) if !handle.is_done()? => None,
+                _ => Some(State::Done),
+            },
+            State::Done => None,
+        })
+    }
+}
+
+impl Default for PushBlockMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,181 @@
+use crate::cond::CondDef;
+use crate::robo::{Retry, Robot, Task, TaskFunc};
+use crate::settings::RetryPolicy;
+use ev3dev_lang_rust::{Ev3Error, Ev3Result};
+use std::error::Error;
+
+pub type SharedFunc<Res> = std::sync::Arc<dyn Fn(&mut Robot) -> Ev3Result<Res> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Entry<Res> {
+    pub name: String,
+    pub description: String,
+    /// settings keys the function reads
+    pub settings: &'static [&'static str],
+    pub func: SharedFunc<Res>,
+}
+
+/// Actions and conditions by name, so tasks can be built from the CLI or a mission file.
+#[derive(Clone, Default)]
+pub struct Registry {
+    pub actions: Vec<Entry<()>>,
+    pub conds: Vec<Entry<bool>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn action(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        settings: &'static [&'static str],
+        func: fn(&mut Robot) -> Ev3Result<()>,
+    ) -> &mut Self {
+        self.actions.push(Entry {
+            name: name.to_string(),
+            description: description.to_string(),
+            settings,
+            func: std::sync::Arc::new(func),
+        });
+        self
+    }
+
+    pub fn cond(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        settings: &'static [&'static str],
+        func: fn(&mut Robot) -> Ev3Result<bool>,
+    ) -> &mut Self {
+        self.conds.push(Entry {
+            name: name.to_string(),
+            description: description.to_string(),
+            settings,
+            func: std::sync::Arc::new(func),
+        });
+        self
+    }
+
+    /// an action that is not a plain function, e.g. from a script,
+    /// fails if `name` is taken since `get_action` would never find it
+    pub fn add_action(
+        &mut self,
+        name: String,
+        description: String,
+        func: SharedFunc<()>,
+    ) -> Ev3Result<()> {
+        if self.actions.iter().any(|entry| entry.name == name) {
+            return Err(Ev3Error::InternalError {
+                msg: format!("there already is an action `{}`", name),
+            });
+        }
+        self.actions.push(Entry {
+            name,
+            description,
+            settings: &[],
+            func,
+        });
+        Ok(())
+    }
+
+    /// a condition that is not a plain function, e.g. from a script,
+    /// fails if `name` is taken since `get_cond` would never find it
+    pub fn add_cond(
+        &mut self,
+        name: String,
+        description: String,
+        func: SharedFunc<bool>,
+    ) -> Ev3Result<()> {
+        if self.conds.iter().any(|entry| entry.name == name) {
+            return Err(Ev3Error::InternalError {
+                msg: format!("there already is a condition `{}`", name),
+            });
+        }
+        self.conds.push(Entry {
+            name,
+            description,
+            settings: &[],
+            func,
+        });
+        Ok(())
+    }
+
+    /// `name` with or without the `act_` prefix
+    pub fn get_action(&self, name: &str) -> Option<TaskFunc<()>> {
+        let name = name.strip_prefix("act_").unwrap_or(name);
+        self.actions
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| {
+                let func = entry.func.clone();
+                Box::new(move |robo: &mut Robot| func(robo)) as TaskFunc<()>
+            })
+    }
+
+    /// `name` with or without the `cond_` prefix
+    pub fn get_cond(&self, name: &str) -> Option<TaskFunc<bool>> {
+        let name = name.strip_prefix("cond_").unwrap_or(name);
+        self.conds
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| {
+                let func = entry.func.clone();
+                Box::new(move |robo: &mut Robot| func(robo)) as TaskFunc<bool>
+            })
+    }
+
+    /// `policy` with its condition and action names looked up
+    pub fn retry(&self, policy: &RetryPolicy) -> Ev3Result<Retry> {
+        Ok(Retry {
+            attempts: policy.attempts,
+            success: self.get_cond(&policy.success).ok_or(Ev3Error::InternalError {
+                msg: format!("unknown condition `{}`, see --list-tasks", policy.success),
+            })?,
+            recovery: match &policy.recovery {
+                Some(recovery) => Some(self.get_action(recovery).ok_or(
+                    Ev3Error::InternalError {
+                        msg: format!("unknown action `{}`, see --list-tasks", recovery),
+                    },
+                )?),
+                None => None,
+            },
+            fallback: policy.fallback,
+        })
+    }
+
+    pub fn task(&self, name: &str, action: &str, cond: &CondDef) -> Ev3Result<Task> {
+        let act = self.get_action(action).ok_or(Ev3Error::InternalError {
+            msg: format!("unknown action `{}`, see --list-tasks", action),
+        })?;
+        Ok(Task::new(name.to_string(), act, cond.build(self)?))
+    }
+
+    pub fn print(&self) {
+        println!("actions:");
+        for entry in &self.actions {
+            print_entry(entry);
+        }
+        println!("conditions:");
+        for entry in &self.conds {
+            print_entry(entry);
+        }
+    }
+}
+
+fn print_entry<Res>(entry: &Entry<Res>) {
+    println!("  {:<20}{}", entry.name, entry.description);
+    if !entry.settings.is_empty() {
+        println!("  {:<20}settings: {}", "", entry.settings.join(", "));
+    }
+}
+
+/// `action:cond` for `--task`
+pub fn parse_task(s: &str) -> Result<(String, String), Box<dyn Error + Send + Sync + 'static>> {
+    let pos = s
+        .find(':')
+        .ok_or_else(|| format!("invalid ACTION:COND: no `:` found in `{}`", s))?;
+    Ok((s[..pos].to_string(), s[pos + 1..].to_string()))
+}
@@ -1,20 +1,90 @@
-use crate::move_steering::MoveSteering;
-use crate::sensor::LineSensor;
-use crate::settings::Settings;
+use crate::course::{CourseProfile, CourseRecorder};
+use crate::events::{Event, EventBus};
+use crate::marker;
+use crate::monitor::{self, Effect, Monitor};
+use crate::move_steering::{MoveSteering, Ramp};
+use crate::obstacle::ObstacleProfile;
+use crate::odometry::{Odometry, Pose};
+use crate::sensor::{Gyro, LineSensor};
+use crate::splits::Split;
+use crate::settings::{Budget, Fallback, Settings};
 
 use ev3dev_lang_rust::motors::{LargeMotor};
 use ev3dev_lang_rust::sensors::{UltrasonicSensor};
 
 use ev3dev_lang_rust::Ev3Button;
-use ev3dev_lang_rust::Ev3Result;
+use ev3dev_lang_rust::PowerSupply;
+use ev3dev_lang_rust::{Ev3Error, Ev3Result};
+use serde_derive::Deserialize;
 
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub fn sign(&self) -> i32 {
+        match self {
+            Direction::Left => -1,
+            Direction::Right => 1,
+        }
+    }
+}
+
+/// What `follow_line_once` does for one left/middle/right sensor pattern.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LineAction {
+    Forward,
+    /// drive a curve, `steering` defaults to `steering.max_steering`,
+    /// `until_line` keeps the curve (max `turn_timeout`) while the sensor on that side is on
+    /// the line, i.e. until it left the line again
+    Arc {
+        direction: Direction,
+        steering: Option<i32>,
+        #[serde(default)]
+        until_line: bool,
+    },
+    /// turn on the spot
+    Pivot { direction: Direction },
+    /// repeat the previous action
+    Keep,
+    /// turn on the spot towards the last turn direction until the middle sensor finds the line
+    Search,
+}
+
+const BUDGET_EXCEEDED: &str = "budget exceeded";
+
+/// the error `Robot::check_budget` returns
+pub fn budget_error() -> Ev3Error {
+    Ev3Error::InternalError {
+        msg: BUDGET_EXCEEDED.to_string(),
+    }
+}
 
-type TaskFunc<Res> = Box<dyn Fn(&mut Robot) -> Ev3Result<Res> + Send>;
+pub fn is_budget_error(e: &Ev3Error) -> bool {
+    matches!(e, Ev3Error::InternalError { msg } if msg == BUDGET_EXCEEDED)
+}
+
+pub type TaskFunc<Res> = Box<dyn Fn(&mut Robot) -> Ev3Result<Res> + Send>;
+
+/// see `RetryPolicy`
+pub struct Retry {
+    pub attempts: u32,
+    pub success: TaskFunc<bool>,
+    pub recovery: Option<TaskFunc<()>>,
+    pub fallback: Fallback,
+}
 
 pub struct Task {
     pub name: String,
     pub act: TaskFunc<()>,
     pub cond: TaskFunc<bool>,
+    /// limits for `act`, see `Robot::check_budget`
+    pub budget: Budget,
+    pub retry: Option<Retry>,
 }
 
 impl Task {
@@ -23,7 +93,23 @@ impl Task {
         act: TaskFunc<()>,
         cond: TaskFunc<bool>,
     ) -> Self {
-        Self { name, act, cond }
+        Self {
+            name,
+            act,
+            cond,
+            budget: Budget::default(),
+            retry: None,
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = Some(retry);
+        self
     }
 }
 
@@ -33,121 +119,525 @@ pub struct Robot {
     pub right_sensor: LineSensor,
     pub middle_sensor: LineSensor,
     pub us_sensor: UltrasonicSensor,
+    pub gyro: Option<Gyro>,
     pub ball_motor: LargeMotor,
+    pub power: PowerSupply,
     pub speed: i32,
     pub max_steering: i32,
+    pub last_action: LineAction,
+    pub last_turn: Direction,
+    pub odometry: Odometry,
+    pub course_recorder: Option<CourseRecorder>,
+    pub course_profile: Option<CourseProfile>,
+    /// when and where the current `follow_line_loop` started
+    pub follow_started: std::time::Instant,
+    pub follow_started_distance: f32,
+    /// budget of the running task and when and where it started
+    pub budget: Option<(Budget, std::time::Instant, f32)>,
+    pub events: EventBus,
+    /// for `Event::LineLost` and `Event::MarkerDetected`
+    line_lost: bool,
+    marker_published: Option<f32>,
+    /// transitions of the middle sensor over the travelled distance, see `marker`
+    pub marker_history: marker::History,
+    /// end of the last marker a condition fired on, see `marker::matches`
+    pub marker_used: Option<f32>,
+    pub obstacle: ObstacleProfile,
+    /// of the tasks run so far
+    pub splits: Vec<Split>,
+    /// checked by every `check_budget`, see `monitor`
+    pub monitors: Vec<Monitor>,
+    /// name of the task `follow_line_loop` is following the line for or running
+    pub task: Option<String>,
+    /// what the robot is doing right now, for telemetry
+    pub state: std::sync::Arc<std::sync::Mutex<String>>,
     pub settings: Settings,
 }
 
+/// The motors and sensors of a `Robot`.
+pub struct Hardware {
+    pub steering: MoveSteering,
+    pub left_sensor: LineSensor,
+    pub right_sensor: LineSensor,
+    pub middle_sensor: LineSensor,
+    pub us_sensor: UltrasonicSensor,
+    pub gyro: Option<Gyro>,
+    pub ball_motor: LargeMotor,
+    pub power: PowerSupply,
+}
+
 impl Robot {
-    pub fn new(
-        steering: MoveSteering,
-        left_sensor: LineSensor,
-        right_sensor: LineSensor,
-        middle_sensor: LineSensor,
-        us_sensor: UltrasonicSensor,
-        ball_motor: LargeMotor,
-        settings: Settings,
-    ) -> Self {
+    pub fn new(hardware: Hardware, settings: Settings) -> Self {
+        let Hardware {
+            steering,
+            left_sensor,
+            right_sensor,
+            middle_sensor,
+            us_sensor,
+            gyro,
+            ball_motor,
+            power,
+        } = hardware;
         Self {
             steering,
             left_sensor,
             right_sensor,
             middle_sensor,
             us_sensor,
+            gyro,
             ball_motor,
+            power,
             speed: settings.steering.speed,
             max_steering: settings.steering.max_steering,
+            last_action: LineAction::Forward,
+            last_turn: Direction::Right,
+            odometry: Odometry::new(
+                settings.geometry.wheel_diameter,
+                settings.geometry.track_width,
+            ),
+            course_recorder: None,
+            course_profile: None,
+            follow_started: std::time::Instant::now(),
+            follow_started_distance: 0.0,
+            budget: None,
+            events: EventBus::new(),
+            line_lost: false,
+            marker_published: None,
+            marker_history: [None; 16],
+            marker_used: None,
+            obstacle: ObstacleProfile::default(),
+            splits: Vec::new(),
+            monitors: Vec::new(),
+            task: None,
+            state: Default::default(),
             settings,
         }
     }
 
+    pub fn update_odometry(&mut self) -> Ev3Result<Pose> {
+        let (left, right) = self.steering.get_positions()?;
+        Ok(self
+            .odometry
+            .update(left, right, self.steering.count_per_rot))
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.odometry.pose()
+    }
+
     pub fn forward(&mut self) -> Ev3Result<()> {
         self.steering.on(0, self.speed)?;
         Ok(())
     }
 
-    pub fn turn_right_until_line(&mut self) -> Ev3Result<()> {
-        self.steering.on(self.max_steering, self.speed)?;
-        self.right_sensor.wait_for_line(true, std::time::Duration::from_millis(self.settings.steering.turn_timeout))?;
-        //self.steering.off()?;
+    pub fn add_monitor(&mut self, monitor: Monitor) {
+        self.monitors.push(monitor);
+    }
+
+    pub fn set_state(&self, state: &str) {
+        *self.state.lock().unwrap() = state.to_string();
+    }
+
+    /// replace the settings (e.g. for one mission step), returns the old ones,
+    /// the steering model, ramp, stop action and wheel sync apply to `steering` right away
+    pub fn set_settings(&mut self, settings: Settings) -> Ev3Result<Settings> {
+        self.speed = settings.steering.speed;
+        self.max_steering = settings.steering.max_steering;
+        self.steering.model = settings.steering.model;
+        self.steering.sync_gain = settings.steering.sync_gain;
+        self.steering.stop_action = settings.steering.stop_action;
+        self.steering.set_stop_action(settings.steering.stop_action)?;
+        self.steering.ramp = settings.steering.ramp;
+        self.steering.set_ramp(settings.steering.ramp)?;
+        Ok(std::mem::replace(&mut self.settings, settings))
+    }
+
+    /// run `action` with a different ramp than `steering.ramp`,
+    /// the default is restored when `action` returns, so use blocking moves
+    pub fn with_ramp<T>(
+        &mut self,
+        ramp: Option<Ramp>,
+        action: impl FnOnce(&mut Self) -> Ev3Result<T>,
+    ) -> Ev3Result<T> {
+        let ramp = match ramp {
+            Some(ramp) => ramp,
+            None => return action(self),
+        };
+        self.steering.set_ramp(ramp)?;
+        let res = action(self);
+        self.steering.set_ramp(self.steering.ramp)?;
+        res
+    }
+
+    /// turn on the spot by `angle` degrees (positive = right) using the gyro
+    pub fn turn_angle(&mut self, angle: f32, speed: i32) -> Ev3Result<()> {
+        let gyro = self.gyro.as_ref().ok_or(Ev3Error::InternalError {
+            msg: "no gyro configured".to_string(),
+        })?;
+        let settings = &self.settings.gyro;
+        let start = gyro.angle()?;
+        let started = std::time::Instant::now();
+        let timeout = std::time::Duration::from_millis(settings.timeout);
+        loop {
+            let remaining = angle - (gyro.angle()? - start) as f32;
+            if remaining.abs() <= settings.tolerance || started.elapsed() > timeout {
+                break;
+            }
+            // slow down near the target to not overshoot
+            let speed = if remaining.abs() < settings.slow_down_angle {
+                ((speed as f32 * remaining.abs() / settings.slow_down_angle) as i32)
+                    .max(settings.min_speed)
+            } else {
+                speed
+            };
+            self.steering.spin(remaining.signum() as i32 * speed)?;
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        self.steering.off()?;
         Ok(())
     }
 
-    pub fn turn_left_until_line(&mut self) -> Ev3Result<()> {
-        self.steering.on(-self.max_steering, self.speed)?;
-        self.left_sensor.wait_for_line(true, std::time::Duration::from_millis(self.settings.steering.turn_timeout))?;
-        //self.steering.off()?;
+    /// turn on the spot by `angle` degrees (positive = right) with the gyro if there is one,
+    /// otherwise by the wheel geometry
+    pub fn turn_degrees(&mut self, angle: f32, speed: i32) -> Ev3Result<()> {
+        match self.gyro {
+            Some(_) => self.turn_angle(angle, speed),
+            None => {
+                self.steering.turn_degrees(angle, speed, None, true)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// turn by `angle` degrees if there is a gyro, otherwise (or without an angle)
+    /// by the tuned `rotations`
+    pub fn turn(
+        &mut self,
+        steering: i32,
+        speed: i32,
+        rotations: f32,
+        angle: Option<f32>,
+    ) -> Ev3Result<()> {
+        match angle {
+            Some(angle) if self.gyro.is_some() => {
+                self.turn_angle(steering.signum() as f32 * angle, speed)?;
+            }
+            _ => {
+                self.steering
+                    .on_for_rotations(steering, speed, rotations, true)?;
+            }
+        }
         Ok(())
     }
 
     pub fn follow_line_loop(&mut self, task: Option<Task>) -> Ev3Result<()> {
         let button = Ev3Button::new()?;
-        match task {
-            Some(task) => loop {
-                button.process();
-                if !button.get_pressed_buttons().is_empty() {
-                    self.steering.off()?;
-                    break;
+        self.follow_started = std::time::Instant::now();
+        self.follow_started_distance = self.update_odometry()?.distance;
+        self.task = task.as_ref().map(|task| task.name.clone());
+        let res = self.follow_line_until(task.as_ref(), &button);
+        self.task = None;
+        match res {
+            Err(e) if monitor::is_preempt_error(&e) => {
+                self.steering.off()?;
+                if let Some(task) = &task {
+                    self.events.publish(Event::TaskFailed {
+                        name: task.name.clone(),
+                        error: "preempted by a monitor, skipped".to_string(),
+                    });
                 }
-                if (task.cond)(self).unwrap_or(false) {
-                    println!("Task {} started", task.name);
-                    (task.act)(self)?;
-                    println!("Task {} finished", task.name);
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    fn follow_line_until(&mut self, task: Option<&Task>, button: &Ev3Button) -> Ev3Result<()> {
+        loop {
+            button.process();
+            if !button.get_pressed_buttons().is_empty() {
+                self.steering.off()?;
+                break;
+            }
+            // the monitors, before the condition so a preempted task does not run
+            self.check_budget()?;
+            if let Some(task) = task {
+                if self.task_armed(&task.name) && (task.cond)(self).unwrap_or(false) {
+                    let event = Event::ConditionTriggered {
+                        name: task.name.clone(),
+                        distance: self.us_sensor.get_distance_centimeters()?,
+                        lines: [
+                            self.left_sensor.on_line(),
+                            self.middle_sensor.on_line(),
+                            self.right_sensor.on_line(),
+                        ],
+                    };
+                    self.events.publish(event);
+                    let follow = self.follow_started.elapsed();
+                    let started = std::time::Instant::now();
+                    self.run_task(task)?;
+                    self.splits.push(Split {
+                        task: task.name.clone(),
+                        follow,
+                        action: started.elapsed(),
+                    });
                     break;
                 }
-                self.follow_line_once()?;
-            },
-            None => loop {
-                button.process();
-                if !button.get_pressed_buttons().is_empty() {
-                    self.steering.off()?;
-                    break;
+            }
+            self.follow_line_step()?;
+        }
+        Ok(())
+    }
+
+    fn run_task(&mut self, task: &Task) -> Ev3Result<()> {
+        self.events.publish(Event::TaskStarted {
+            name: task.name.clone(),
+        });
+        let started = std::time::Instant::now();
+        let distance = self.pose().distance;
+        if let Some(recorder) = self.course_recorder.as_mut() {
+            recorder.record_task(distance, &task.name);
+        }
+        self.budget = Some((task.budget, started, distance));
+        let (res, attempts) = self.attempt_task(task);
+        self.budget = None;
+        self.update_odometry()?;
+        match res {
+            Err(e) if monitor::is_preempt_error(&e) => {
+                self.steering.off()?;
+                self.events.publish(Event::TaskFailed {
+                    name: task.name.clone(),
+                    error: "preempted by a monitor, skipped".to_string(),
+                });
+            }
+            Err(e) if is_budget_error(&e) => {
+                self.steering.off()?;
+                let stop = task.budget.fallback == Fallback::Stop;
+                self.events.publish(Event::TaskFailed {
+                    name: task.name.clone(),
+                    error: format!(
+                        "budget exceeded, {}",
+                        if stop { "stopped" } else { "skipped" }
+                    ),
+                });
+                if stop {
+                    self.ball_motor.stop()?;
+                    return Err(Ev3Error::InternalError {
+                        msg: format!("task {} exceeded its budget", task.name),
+                    });
+                }
+            }
+            Ok(false) => {
+                self.steering.off()?;
+                let stop = task
+                    .retry
+                    .as_ref()
+                    .is_some_and(|retry| retry.fallback == Fallback::Stop);
+                self.events.publish(Event::TaskFailed {
+                    name: task.name.clone(),
+                    error: format!(
+                        "no success after {} attempts, {}",
+                        attempts,
+                        if stop { "stopped" } else { "skipped" }
+                    ),
+                });
+                if stop {
+                    self.ball_motor.stop()?;
+                    return Err(Ev3Error::InternalError {
+                        msg: format!("task {} failed", task.name),
+                    });
                 }
-                self.follow_line_once()?;
-            },
+            }
+            Err(e) => {
+                self.events.publish(Event::TaskFailed {
+                    name: task.name.clone(),
+                    error: format!("{:?}", e),
+                });
+                return Err(e);
+            }
+            Ok(true) => self.events.publish(Event::TaskFinished {
+                name: task.name.clone(),
+                duration: started.elapsed(),
+                attempts,
+            }),
         }
         Ok(())
     }
 
+    /// run the action, then while its success check fails the recovery, or the action again
+    /// if there is none, returns if it succeeded and the attempts
+    fn attempt_task(&mut self, task: &Task) -> (Ev3Result<bool>, u32) {
+        let mut attempt = 1;
+        if let Err(e) = (task.act)(self) {
+            return (Err(e), attempt);
+        }
+        let retry = match &task.retry {
+            Some(retry) => retry,
+            None => return (Ok(true), attempt),
+        };
+        loop {
+            match (retry.success)(self) {
+                Ok(true) => return (Ok(true), attempt),
+                Ok(false) => {}
+                Err(e) => return (Err(e), attempt),
+            }
+            if attempt >= retry.attempts {
+                return (Ok(false), attempt);
+            }
+            attempt += 1;
+            self.events.publish(Event::TaskRetry {
+                name: task.name.clone(),
+                attempt,
+                attempts: retry.attempts,
+            });
+            // only repeat what failed, the action may have left the robot somewhere else
+            let res = match &retry.recovery {
+                Some(recovery) => recovery(self),
+                None => (task.act)(self),
+            };
+            if let Err(e) = res {
+                return (Err(e), attempt);
+            }
+        }
+    }
+
+    /// true when the running task is over its time or distance budget
+    pub fn budget_exceeded(&self) -> bool {
+        match &self.budget {
+            Some((budget, started, distance)) => {
+                budget
+                    .time
+                    .is_some_and(|ms| started.elapsed().as_millis() as u64 > ms)
+                    || budget
+                        .distance
+                        .is_some_and(|max| (self.pose().distance - distance).abs() > max)
+            }
+            None => false,
+        }
+    }
+
+    /// call this in the loops of actions, fails when the running task is over its budget
+    /// so that `follow_line_loop` can run the fallback, also checks the monitors:
+    /// blocks with stopped motors while one pauses and fails when one preempts the task
+    pub fn check_budget(&mut self) -> Ev3Result<()> {
+        let mut paused = false;
+        loop {
+            if self.budget.is_some() {
+                self.update_odometry()?;
+            }
+            if self.budget_exceeded() {
+                return Err(budget_error());
+            }
+            let task = self.task.clone();
+            match monitor::check(self, task.as_deref())? {
+                Some(Effect::Preempt) => return Err(monitor::preempt_error()),
+                Some(Effect::Pause) => {
+                    if !paused {
+                        self.steering.off()?;
+                        paused = true;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                Some(Effect::Annotate) | None => return Ok(()),
+            }
+        }
+    }
+
+    /// false while a learned course says the task is still far away
+    pub fn task_armed(&self, name: &str) -> bool {
+        match &self.course_profile {
+            Some(profile) => {
+                profile.task_armed(name, self.pose().distance, self.settings.course.arm_window)
+            }
+            None => true,
+        }
+    }
+
     pub fn follow_line_once(&mut self) -> Ev3Result<()> {
-        if self.left_sensor.off_line()
-            && self.middle_sensor.on_line()
-            && self.right_sensor.off_line()
-        {
-            self.forward()?;
-        } else if self.left_sensor.off_line()
-            && self.middle_sensor.on_line()
-            && self.right_sensor.on_line()
-        {
-            self.turn_right_until_line()?;
-            //self.steering.on(self.max_steering, self.speed)?;
-        } else if self.left_sensor.on_line()
-            && self.middle_sensor.on_line()
-            && self.right_sensor.off_line()
-        {
-            self.turn_left_until_line()?;
-            //self.steering.on(-self.max_steering, self.speed)?;
-        } else if self.left_sensor.off_line()
-            && self.middle_sensor.off_line()
-            && self.right_sensor.off_line()
-        {
-            self.forward()?;
-        } else if self.left_sensor.on_line()
-            && self.middle_sensor.off_line()
-            && self.right_sensor.off_line()
-        {
-            self.turn_left_until_line()?;
-            //self.steering.on(-self.max_steering, self.speed)?;
-        } else if self.left_sensor.off_line()
-            && self.middle_sensor.off_line()
-            && self.right_sensor.on_line()
-        {
-            self.turn_right_until_line()?;
-            //self.steering.on(self.max_steering, self.speed)?;
-        } else {
-            self.forward()?;
+        self.check_budget()?;
+        self.follow_line_step()
+    }
+
+    /// `follow_line_once` without `check_budget`, for loops that check it themselves
+    pub fn follow_line_step(&mut self) -> Ev3Result<()> {
+        let pose = self.update_odometry()?;
+        let distance = self.us_sensor.get_distance_centimeters()?;
+        self.obstacle
+            .sample(pose.distance, distance, &self.settings.obstacle);
+        if let Some(profile) = &self.course_profile {
+            // never faster than `steering.speed`, which mission steps may have lowered
+            self.speed = profile
+                .speed_at(pose.distance, &self.settings.course)
+                .min(self.settings.steering.speed);
+        }
+        let left = self.left_sensor.on_line();
+        let middle = self.middle_sensor.on_line();
+        let right = self.right_sensor.on_line();
+        let line_lost = !left && !middle && !right;
+        if line_lost && !self.line_lost {
+            self.events.publish(Event::LineLost);
+        }
+        self.line_lost = line_lost;
+        marker::record(&mut self.marker_history, middle, pose.distance);
+        let markers = &self.settings.markers;
+        if let Some(marker) = marker::decode(&self.marker_history, pose.distance, markers) {
+            // stripes that match no pattern are usually just curves or crossings
+            if let Some(pattern) = marker::find(&marker, markers) {
+                if self.marker_published != Some(marker.end) {
+                    self.marker_published = Some(marker.end);
+                    let pattern = pattern.to_string();
+                    self.events.publish(Event::MarkerDetected {
+                        stripes: marker.stripes,
+                        pattern,
+                    });
+                }
+            }
+        }
+        let action = self.settings.follow_line.action(left, middle, right);
+        self.run_line_action(action)
+    }
+
+    pub fn run_line_action(&mut self, action: LineAction) -> Ev3Result<()> {
+        let action = match action {
+            LineAction::Keep => self.last_action,
+            action => action,
+        };
+        match action {
+            LineAction::Forward | LineAction::Keep => self.forward()?,
+            LineAction::Arc {
+                direction,
+                steering,
+                until_line,
+            } => {
+                let steering = steering.unwrap_or(self.max_steering).abs();
+                self.steering.on(direction.sign() * steering, self.speed)?;
+                if until_line {
+                    let timeout =
+                        std::time::Duration::from_millis(self.settings.steering.turn_timeout);
+                    match direction {
+                        Direction::Left => self.left_sensor.wait_for_line(true, timeout)?,
+                        Direction::Right => self.right_sensor.wait_for_line(true, timeout)?,
+                    }
+                }
+                self.last_turn = direction;
+            }
+            LineAction::Pivot { direction } => {
+                self.steering.spin(direction.sign() * self.speed)?;
+                self.last_turn = direction;
+            }
+            LineAction::Search => {
+                // spin towards the side the line was last seen on until the middle sensor finds it
+                self.steering.spin(self.last_turn.sign() * self.speed)?;
+                self.middle_sensor.wait_for_line(
+                    false,
+                    std::time::Duration::from_millis(self.settings.steering.turn_timeout),
+                )?;
+            }
+        }
+        if let Some(recorder) = self.course_recorder.as_mut() {
+            recorder.record_action(self.odometry.pose().distance, &action);
         }
+        self.last_action = action;
         Ok(())
     }
 }
@@ -0,0 +1,395 @@
+//! Actions and conditions written in Rhai (https://rhai.rs), loaded at startup,
+//! so they can be changed on the brick without cross-compiling.
+//!
+//! Every `fn act_<name>(robo)` in a script becomes the action `<name>`,
+//! every `fn cond_<name>(robo)` (returning a bool) the condition `<name>`, see `scripts/example.rhai`.
+
+use crate::registry::Registry;
+use crate::robo::{self, Robot};
+use crate::sensor::LineSensor;
+
+use ev3dev_lang_rust::motors::LargeMotor;
+use ev3dev_lang_rust::{Ev3Error, Ev3Result};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+use std::any::Any;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+
+pub const SCRIPTS_DIR: &str = "/home/robot/SDP2022/rs/scripts";
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// something a script wants done with the robot and where to send the result
+type Request = (
+    Box<dyn FnOnce(&mut Robot) -> ScriptResult<Box<dyn Any + Send>> + Send>,
+    Sender<ScriptResult<Box<dyn Any + Send>>>,
+);
+
+enum Message {
+    Robot(Request),
+    Done(ScriptResult<Dynamic>),
+}
+
+/// The robot as seen by a script.
+///
+/// Rhai values have to be `'static`, so they cannot borrow the robot: `call` runs the
+/// script on its own thread and does the requests on the thread that owns the robot.
+/// A handle that outlives the call only returns errors.
+#[derive(Clone)]
+struct ScriptRobot(Sender<Message>);
+
+impl ScriptRobot {
+    fn run<T: Send + 'static>(
+        &mut self,
+        func: impl FnOnce(&mut Robot) -> ScriptResult<T> + Send + 'static,
+    ) -> ScriptResult<T> {
+        let gone =
+            || -> Box<EvalAltResult> { "the robot is only available during the call".into() };
+        let (reply, result) = channel();
+        let func = Box::new(move |robo: &mut Robot| {
+            func(robo).map(|value| Box::new(value) as Box<dyn Any + Send>)
+        });
+        self.0
+            .send(Message::Robot((func, reply)))
+            .map_err(|_| gone())?;
+        let value = result.recv().map_err(|_| gone())??;
+        Ok(*value
+            .downcast::<T>()
+            .expect("requests return what they were asked for"))
+    }
+}
+
+fn err(e: Ev3Error) -> Box<EvalAltResult> {
+    format!("{:?}", e).into()
+}
+
+fn line_sensor<'a>(robo: &'a mut Robot, name: &str) -> ScriptResult<&'a mut LineSensor> {
+    match name {
+        "left" => Ok(&mut robo.left_sensor),
+        "middle" => Ok(&mut robo.middle_sensor),
+        "right" => Ok(&mut robo.right_sensor),
+        _ => Err(format!("unknown line sensor `{}`, use left, middle or right", name).into()),
+    }
+}
+
+/// the functions scripts can call on `robo`
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .register_type_with_name::<ScriptRobot>("Robot")
+        .register_fn("follow_line_once", |r: &mut ScriptRobot| {
+            r.run(|robo| robo.follow_line_once().map_err(err))
+        })
+        .register_fn(
+            "steering_on",
+            |r: &mut ScriptRobot, steering: i64, speed: i64| {
+                r.run(move |robo| robo.steering.on(steering as i32, speed as i32).map_err(err))
+            },
+        )
+        .register_fn("off", |r: &mut ScriptRobot| {
+            r.run(|robo| robo.steering.off().map_err(err))
+        })
+        .register_fn(
+            "on_for_rotations",
+            |r: &mut ScriptRobot, steering: i64, speed: i64, rotations: f64| {
+                r.run(move |robo| {
+                    robo.steering
+                        .on_for_rotations(steering as i32, speed as i32, rotations as f32, true)
+                        .map(|_| ())
+                        .map_err(err)
+                })
+            },
+        )
+        .register_fn(
+            "on_for_seconds",
+            |r: &mut ScriptRobot, steering: i64, speed: i64, seconds: f64| {
+                r.run(move |robo| {
+                    robo.steering
+                        .on_for_seconds(steering as i32, speed as i32, seconds as f32, true)
+                        .map(|_| ())
+                        .map_err(err)
+                })
+            },
+        )
+        .register_fn("distance", |r: &mut ScriptRobot| {
+            r.run(|robo| {
+                robo.us_sensor
+                    .get_distance_centimeters()
+                    .map(|distance| distance as f64)
+                    .map_err(err)
+            })
+        })
+        .register_fn("travelled", |r: &mut ScriptRobot| {
+            r.run(|robo| {
+                robo.update_odometry()
+                    .map(|pose| pose.distance as f64)
+                    .map_err(err)
+            })
+        })
+        .register_fn(
+            "on_line",
+            |r: &mut ScriptRobot, sensor: &str| -> ScriptResult<bool> {
+                let sensor = sensor.to_string();
+                r.run(move |robo| Ok(line_sensor(robo, &sensor)?.on_line()))
+            },
+        )
+        .register_fn(
+            "wait_for_line",
+            |r: &mut ScriptRobot, sensor: &str, on_line: bool, timeout: i64| -> ScriptResult<()> {
+                let sensor = sensor.to_string();
+                r.run(move |robo| {
+                    line_sensor(robo, &sensor)?
+                        .wait_for_line(on_line, std::time::Duration::from_millis(timeout as u64))
+                        .map_err(err)
+                })
+            },
+        )
+        .register_fn(
+            "ball_motor_to",
+            |r: &mut ScriptRobot, position: i64, speed: i64| -> ScriptResult<()> {
+                r.run(move |robo| {
+                    let ball_motor = &robo.ball_motor;
+                    ball_motor
+                        .set_stop_action(LargeMotor::STOP_ACTION_HOLD)
+                        .map_err(err)?;
+                    ball_motor.set_speed_sp(speed as i32).map_err(err)?;
+                    ball_motor
+                        .run_to_rel_pos(Some(position as i32))
+                        .map_err(err)?;
+                    ball_motor.wait_until_not_moving(Some(std::time::Duration::from_secs(2)));
+                    Ok(())
+                })
+            },
+        )
+        .register_fn("ball_motor_forever", |r: &mut ScriptRobot, speed: i64| {
+            r.run(move |robo| {
+                robo.ball_motor.set_speed_sp(speed as i32).map_err(err)?;
+                robo.ball_motor.run_forever().map_err(err)
+            })
+        })
+        .register_fn("ball_motor_stop", |r: &mut ScriptRobot| {
+            r.run(|robo| robo.ball_motor.stop().map_err(err))
+        })
+        .register_fn("check_budget", |r: &mut ScriptRobot| {
+            r.run(|robo| robo.check_budget().map_err(err))
+        })
+        .register_fn("sleep", |ms: i64| {
+            std::thread::sleep(std::time::Duration::from_millis(ms as u64))
+        });
+    engine
+}
+
+/// true if `e` comes from `check_budget`, so `Robot::run_task` runs the budget fallback
+fn is_budget_error(e: &EvalAltResult) -> bool {
+    match e {
+        EvalAltResult::ErrorInFunctionCall(_, _, e, _) => is_budget_error(e),
+        EvalAltResult::ErrorRuntime(value, _) => {
+            value.to_string() == format!("{:?}", robo::budget_error())
+        }
+        _ => false,
+    }
+}
+
+/// run the script function `name` on its own thread and do what it asks of the robot here
+fn call(engine: &Engine, ast: &AST, name: &str, robo: &mut Robot) -> Ev3Result<Dynamic> {
+    let (messages, queue) = channel();
+    let res = std::thread::scope(|scope| {
+        let done = messages.clone();
+        let script = scope.spawn(move || {
+            let res =
+                engine.call_fn::<Dynamic>(&mut Scope::new(), ast, name, (ScriptRobot(messages),));
+            let _ = done.send(Message::Done(res));
+        });
+        let mut res = None;
+        // ends early only if the script thread panicked
+        for message in &queue {
+            match message {
+                Message::Robot((func, reply)) => {
+                    let _ = reply.send(func(robo));
+                }
+                Message::Done(done) => {
+                    res = Some(done);
+                    break;
+                }
+            }
+        }
+        let _ = script.join();
+        res
+    });
+    let res = res.ok_or_else(|| Ev3Error::InternalError {
+        msg: format!("script {} panicked", name),
+    })?;
+    res.map_err(|e| {
+        if is_budget_error(&e) {
+            return robo::budget_error();
+        }
+        Ev3Error::InternalError {
+            msg: format!("script {}: {}", name, e),
+        }
+    })
+}
+
+/// add the actions and conditions of the script `file` to `registry`
+pub fn load(registry: &mut Registry, file: &std::path::Path) -> Ev3Result<()> {
+    let engine = Arc::new(engine());
+    let ast = Arc::new(
+        engine
+            .compile_file(file.into())
+            .map_err(|e| Ev3Error::InternalError {
+                msg: format!("failed to load script {}: {}", file.display(), e),
+            })?,
+    );
+    add(registry, engine, ast, &file.display().to_string());
+    Ok(())
+}
+
+/// add the `act_`/`cond_` functions of `ast`, compiled from `source`, to `registry`
+fn add(registry: &mut Registry, engine: Arc<Engine>, ast: Arc<AST>, source: &str) {
+    let description = format!("script {}", source);
+    for function in ast.iter_functions() {
+        if function.params.len() != 1 {
+            continue;
+        }
+        let name = function.name.to_string();
+        let added = if let Some(action) = name.strip_prefix("act_") {
+            let (engine, ast, name) = (engine.clone(), ast.clone(), name.clone());
+            registry.add_action(
+                action.to_string(),
+                description.clone(),
+                Arc::new(move |robo| call(&engine, &ast, &name, robo).map(|_| ())),
+            )
+        } else if let Some(cond) = name.strip_prefix("cond_") {
+            let (engine, ast, name) = (engine.clone(), ast.clone(), name.clone());
+            registry.add_cond(
+                cond.to_string(),
+                description.clone(),
+                Arc::new(move |robo| {
+                    call(&engine, &ast, &name, robo)?.as_bool().map_err(|kind| {
+                        Ev3Error::InternalError {
+                            msg: format!("script {} returned {} instead of a bool", name, kind),
+                        }
+                    })
+                }),
+            )
+        } else {
+            continue;
+        };
+        // a builtin with the same name would always win
+        if let Err(e) = added {
+            println!("skipping {} in {}: {:?}", name, source, e);
+        }
+    }
+}
+
+/// load every `.rhai` file in `SCRIPTS_DIR`, then `files`, scripts that fail to compile are skipped
+pub fn load_all(registry: &mut Registry, files: &[std::path::PathBuf]) {
+    let mut scripts = std::fs::read_dir(SCRIPTS_DIR)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    scripts.sort();
+    for file in scripts.iter().chain(files) {
+        if let Err(e) = load(registry, file) {
+            println!("skipping script {}: {:?}", file.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nothing(_: &mut Robot) -> Ev3Result<()> {
+        Ok(())
+    }
+
+    fn add_source(registry: &mut Registry, source: &str) {
+        let engine = engine();
+        let ast = engine.compile(source).unwrap();
+        add(registry, Arc::new(engine), Arc::new(ast), "test");
+    }
+
+    fn description<'a, T>(entries: &'a [crate::registry::Entry<T>], name: &str) -> Option<&'a str> {
+        entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.description.as_str())
+    }
+
+    #[test]
+    fn adds_actions_and_conditions() {
+        let mut registry = Registry::new();
+        registry.action("stop", "builtin", &[], nothing);
+        add_source(
+            &mut registry,
+            r#"
+            fn act_stop(robo) { robo.off(); }
+            fn act_wiggle(robo) { robo.steering_on(10, 20); }
+            fn cond_close(robo) { robo.distance() < 10.0 }
+            fn act_helper(robo, speed) { }
+            fn cond_shadow(robo) { false }
+            fn cond_shadow(robo, a) { true }
+            fn other(robo) { }
+            "#,
+        );
+        // taken names stay with the builtin
+        assert_eq!(registry.actions.iter().filter(|a| a.name == "stop").count(), 1);
+        assert_eq!(description(&registry.actions, "stop"), Some("builtin"));
+        assert_eq!(description(&registry.actions, "wiggle"), Some("script test"));
+        assert_eq!(description(&registry.conds, "close"), Some("script test"));
+        assert_eq!(description(&registry.conds, "shadow"), Some("script test"));
+        // only functions with just `robo` count
+        assert_eq!(description(&registry.actions, "helper"), None);
+        assert_eq!(registry.actions.len(), 2);
+        assert_eq!(registry.conds.len(), 2);
+    }
+
+    #[test]
+    fn load_all_skips_broken_scripts() {
+        let dir = std::env::temp_dir();
+        let broken = dir.join(format!("broken-{}.rhai", std::process::id()));
+        let fine = dir.join(format!("fine-{}.rhai", std::process::id()));
+        std::fs::write(&broken, "fn act_broken(robo) { let = ; }").unwrap();
+        std::fs::write(&fine, "fn act_fine_after_broken(robo) { robo.off(); }").unwrap();
+        let mut registry = Registry::new();
+        load_all(&mut registry, &[broken.clone(), fine.clone()]);
+        std::fs::remove_file(&broken).unwrap();
+        std::fs::remove_file(&fine).unwrap();
+        assert!(registry.get_action("fine_after_broken").is_some());
+        assert!(registry.get_action("broken").is_none());
+    }
+
+    #[test]
+    fn budget_error_through_scripts() {
+        let mut engine = Engine::new();
+        engine
+            .register_fn("check_budget", || -> ScriptResult<()> {
+                Err(err(robo::budget_error()))
+            })
+            .register_fn("fail", || -> ScriptResult<()> {
+                Err(err(Ev3Error::InternalError {
+                    msg: "something else".to_string(),
+                }))
+            });
+        let ast = engine
+            .compile(
+                r#"
+                fn act_budget() { check_budget(); }
+                fn act_nested() { act_budget(); }
+                fn act_fail() { fail(); }
+                "#,
+            )
+            .unwrap();
+        let run = |name: &str| {
+            engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &ast, name, ())
+                .unwrap_err()
+        };
+        assert!(is_budget_error(&run("act_budget")));
+        assert!(is_budget_error(&run("act_nested")));
+        assert!(!is_budget_error(&run("act_fail")));
+    }
+}
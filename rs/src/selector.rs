@@ -0,0 +1,69 @@
+//! Pick the step to start a run at, with `--start-at` or the brick buttons.
+
+use ev3dev_lang_rust::{sound, Ev3Button, Ev3Error, Ev3Result};
+
+/// index of the step with this name or action, or a 1-based step number
+pub fn find_step(steps: &[(String, String)], start_at: &str) -> Ev3Result<usize> {
+    if let Ok(number) = start_at.parse::<usize>() {
+        if (1..=steps.len()).contains(&number) {
+            return Ok(number - 1);
+        }
+    }
+    steps
+        .iter()
+        .position(|(name, action)| name == start_at || action == start_at)
+        .ok_or(Ev3Error::InternalError {
+            msg: format!(
+                "no step `{}`, steps: {}",
+                start_at,
+                steps
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        })
+}
+
+/// cycle through the steps with up/down or left/right, the centre button starts,
+/// every step has its own tone, higher for later steps
+pub fn select_step(steps: &[(String, String)], start: usize) -> Ev3Result<usize> {
+    let button = Ev3Button::new()?;
+    let mut index = start;
+    announce(steps, index)?;
+    loop {
+        button.process();
+        if button.is_enter() {
+            break;
+        }
+        let next = if button.is_down() || button.is_right() {
+            (index + 1) % steps.len()
+        } else if button.is_up() || button.is_left() {
+            (index + steps.len() - 1) % steps.len()
+        } else {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            continue;
+        };
+        index = next;
+        announce(steps, index)?;
+        wait_for_release(&button);
+    }
+    sound::tone_sequence(&[(880.0, 100, 50), (880.0, 100, 0)])?.wait()?;
+    // any pressed button stops `follow_line_loop`
+    wait_for_release(&button);
+    Ok(index)
+}
+
+fn announce(steps: &[(String, String)], index: usize) -> Ev3Result<()> {
+    println!("start at {}: {}", index + 1, steps[index].0);
+    sound::tone(440.0 + 110.0 * index as f32, 150)?.wait()?;
+    Ok(())
+}
+
+pub fn wait_for_release(button: &Ev3Button) {
+    button.process();
+    while !button.get_pressed_buttons().is_empty() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        button.process();
+    }
+}
@@ -1,6 +1,27 @@
-use ev3dev_lang_rust::sensors::{ColorSensor, LightSensor, Sensor};
+use ev3dev_lang_rust::sensors::{ColorSensor, GyroSensor, LightSensor, Sensor, SensorPort};
 use std::time::SystemTime;
 use ev3dev_lang_rust::Ev3Result;
+use serde_derive::Deserialize;
+
+/// sensor port as written in the settings
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum InputPort {
+    In1,
+    In2,
+    In3,
+    In4,
+}
+
+impl InputPort {
+    pub fn sensor_port(&self) -> SensorPort {
+        match self {
+            InputPort::In1 => SensorPort::In1,
+            InputPort::In2 => SensorPort::In2,
+            InputPort::In3 => SensorPort::In3,
+            InputPort::In4 => SensorPort::In4,
+        }
+    }
+}
 
 pub enum LineSensorType {
     Light(LightSensor),
@@ -10,6 +31,7 @@ pub enum LineSensorType {
 pub struct LineSensor {
     threshold: i32,
     has_history: bool,
+    /// transitions, newest first: the new state and when it changed
     pub history: [(Option<bool>, Option<SystemTime>); 10],
     sensor: LineSensorType,
 }
@@ -61,3 +83,21 @@ impl LineSensor {
         Ok(())
     }
 }
+
+pub struct Gyro {
+    sensor: GyroSensor,
+    invert: bool,
+}
+
+impl Gyro {
+    pub fn new(sensor: GyroSensor, invert: bool) -> Ev3Result<Self> {
+        sensor.set_mode_gyro_ang()?;
+        Ok(Self { sensor, invert })
+    }
+
+    /// degrees, clockwise (= positive steering) is positive
+    pub fn angle(&self) -> Ev3Result<i32> {
+        let angle = self.sensor.get_angle()?;
+        Ok(if self.invert { -angle } else { angle })
+    }
+}
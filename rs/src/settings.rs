@@ -1,6 +1,9 @@
 use config::Config;
 use serde_derive::Deserialize;
-use crate::move_steering::StopAction;
+use crate::events::EventSettings;
+use crate::move_steering::{Geometry, Ramp, Speed, SteeringModel, StopAction};
+use crate::robo::LineAction;
+use crate::sensor::InputPort;
 use std::error::Error;
 use clap::builder::TypedValueParser as _;
 
@@ -8,7 +11,11 @@ use clap::builder::TypedValueParser as _;
 pub struct Steering {
     pub speed: i32,
     pub max_steering: i32,
+    pub model: SteeringModel,
     pub stop_action: StopAction,
+    pub ramp: Ramp,
+    /// synchronise the wheels while driving straight, see `MoveSteering::sync_gain`
+    pub sync_gain: Option<f32>,
     pub turn_timeout: u64,
 }
 
@@ -18,11 +25,29 @@ pub struct Sensors {
     pub right_threshold: i32,
     pub middle_threshold: i32
 }
+/// optional gyro for turns, `port` unset = no gyro
+#[derive(Debug, Deserialize)]
+pub struct GyroSettings {
+    pub port: Option<InputPort>,
+    pub invert: bool,
+    /// degrees
+    pub tolerance: f32,
+    /// degrees before the target where the speed is reduced
+    pub slow_down_angle: f32,
+    pub min_speed: i32,
+    /// ms
+    pub timeout: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Turn {
     pub steering: i32,
     pub speed: i32,
     pub rotations: f32,
+    /// degrees, used instead of `rotations` if there is a gyro
+    pub angle: Option<f32>,
+    /// overrides `steering.ramp`
+    pub ramp: Option<Ramp>,
 }
 #[derive(Debug, Deserialize)]
 pub struct WaitForBall {
@@ -49,11 +74,27 @@ pub struct PushBlock {
     pub leave_line: UTurn,
     pub u_turn: UTurn,
     pub return_to_line: ReturnToLine,
+    pub timeouts: PushBlockTimeouts,
+}
+
+/// ms per state of `PushBlockMachine`
+#[derive(Debug, Deserialize)]
+pub struct PushBlockTimeouts {
+    pub leave_line: u64,
+    pub approach_block: u64,
+    pub push: u64,
+    pub u_turn: u64,
+    pub return_along_line: u64,
+    pub rejoin_line: u64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct UTurn {
     pub on_for_rotations: OnForRotations,
+    /// degrees, used instead of `on_for_rotations.rotations` if there is a gyro
+    pub angle: Option<f32>,
+    /// overrides `steering.ramp`
+    pub ramp: Option<Ramp>,
     pub drive: Drive,
 }
 
@@ -61,7 +102,13 @@ pub struct UTurn {
 pub struct ReturnToLine {
     pub steering: i32,
     pub speed: i32,
-    pub seconds: i32,
+    pub seconds: f32,
+    /// cm, together with `angle` drives an arc instead of `seconds`
+    pub radius: Option<f32>,
+    /// degrees
+    pub angle: Option<f32>,
+    /// overrides `steering.ramp`
+    pub ramp: Option<Ramp>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -72,12 +119,80 @@ pub struct ThrowBall {
     pub ball_motor_rel_pos: i32,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Party {
+    pub left: Speed,
+    pub right: Speed,
+    pub seconds: f32,
+}
+
+/// what a task does when it runs over its `Budget`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    /// stop the motors and continue with the next task
+    #[default]
+    Skip,
+    /// stop the motors and end the run
+    Stop,
+}
+
+/// limits for one action, unset limits are unlimited
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct Budget {
+    /// ms
+    pub time: Option<u64>,
+    /// cm
+    pub distance: Option<f32>,
+    #[serde(default)]
+    pub fallback: Fallback,
+}
+
+/// check `success` (a condition name) after an action, while it is false run `recovery`
+/// (an action name), or the action again if there is none, `attempts` counts the action too
+#[derive(Debug, Deserialize)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub success: String,
+    pub recovery: Option<String>,
+    /// when all attempts failed
+    #[serde(default)]
+    pub fallback: Fallback,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchLine {
+    pub speed: i32,
+    /// ms
+    pub timeout: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Act {
     pub turn: Turn,
     pub wait_for_ball: WaitForBall,
     pub push_block: PushBlock,
     pub throw_ball: ThrowBall,
+    pub party: Party,
+    pub search_line: SearchLine,
+    /// by action name
+    #[serde(default)]
+    pub budgets: std::collections::BTreeMap<String, Budget>,
+    /// by action name
+    #[serde(default)]
+    pub retries: std::collections::BTreeMap<String, RetryPolicy>,
+}
+
+impl Act {
+    pub fn budget(&self, action: &str) -> Budget {
+        let action = action.strip_prefix("act_").unwrap_or(action);
+        self.budgets.get(action).copied().unwrap_or_default()
+    }
+
+    pub fn retry(&self, action: &str) -> Option<&RetryPolicy> {
+        self.retries
+            .get(action.strip_prefix("act_").unwrap_or(action))
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -90,20 +205,130 @@ pub struct Lines {
 pub struct Cond {
     pub lines: Lines,
     pub dist: f32,
+    /// cm, for the `obstacle` condition
+    pub obstacle: f32,
+    /// V, for the `low_battery` condition
+    pub battery: f32,
+}
+
+/// action for every left_middle_right on/off pattern of the line sensors
+#[derive(Debug, Deserialize)]
+pub struct FollowLine {
+    pub off_off_off: LineAction,
+    pub off_off_on: LineAction,
+    pub off_on_off: LineAction,
+    pub off_on_on: LineAction,
+    pub on_off_off: LineAction,
+    pub on_off_on: LineAction,
+    pub on_on_off: LineAction,
+    pub on_on_on: LineAction,
+}
+
+impl FollowLine {
+    pub fn action(&self, left: bool, middle: bool, right: bool) -> LineAction {
+        match (left, middle, right) {
+            (false, false, false) => self.off_off_off,
+            (false, false, true) => self.off_off_on,
+            (false, true, false) => self.off_on_off,
+            (false, true, true) => self.off_on_on,
+            (true, false, false) => self.on_off_off,
+            (true, false, true) => self.on_off_on,
+            (true, true, false) => self.on_on_off,
+            (true, true, true) => self.on_on_on,
+        }
+    }
+}
+
+/// lap memory, distances in cm along the course
+#[derive(Debug, Deserialize)]
+pub struct Course {
+    pub lookahead: f32,
+    pub merge_gap: f32,
+    pub arm_window: f32,
+    /// both capped by `steering.speed`
+    pub curve_speed: i32,
+    pub straight_speed: i32,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub steering: Steering,
+    pub geometry: Geometry,
     pub sensors: Sensors,
+    pub gyro: GyroSettings,
+    pub follow_line: FollowLine,
     pub act: Act,
     pub cond: Cond,
+    pub course: Course,
+    #[serde(default)]
+    pub events: EventSettings,
+    #[serde(default)]
+    pub splits: Splits,
+    pub obstacle: ObstacleSettings,
+    pub markers: MarkerSettings,
 }
 
+/// stripe widths in cm, see `marker`
+#[derive(Debug, Deserialize)]
+pub struct Pattern {
+    pub stripes: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MarkerSettings {
+    /// relative deviation of a stripe width from the pattern
+    pub tolerance: f32,
+    /// cm, longest distance between two stripes of one marker
+    pub gap: f32,
+    /// cm without a stripe after the last one before the marker counts as complete
+    pub settle: f32,
+    /// cm after which a marker is too old to trigger
+    pub max_age: f32,
+    /// by name, each one is a `marker_<name>` condition
+    #[serde(default)]
+    pub patterns: std::collections::BTreeMap<String, Pattern>,
+}
+
+/// see `obstacle`
+#[derive(Debug, Deserialize)]
+pub struct ObstacleSettings {
+    /// cm travelled the distance profile covers
+    pub window: f32,
+    pub min_samples: usize,
+    /// cm, readings above count as no echo
+    pub max_range: f32,
+    /// minimum cm closer per cm travelled for a standing obstacle
+    pub approach_rate: f32,
+    /// cm, maximum deviation from the approach line for a stable echo
+    pub stability: f32,
+    /// maximum share of readings without echo for a stable echo
+    pub dropouts: f32,
+    /// degrees to each side
+    pub sweep_angle: f32,
+    pub sweep_speed: i32,
+    pub sweep_steps: u32,
+    /// relative deviation from the expected distance that still counts as the same obstacle
+    pub width_tolerance: f32,
+    /// cm, at least this wide is a wall
+    pub wall_width: f32,
+    /// cm, at most this wide is a block
+    pub block_width: f32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Splits {
+    /// split times of every run, see `splits`
+    pub history: Option<std::path::PathBuf>,
+}
+
+/// values saved in the on-brick menu, see `menu`
+pub const MENU_FILE: &str = "/home/robot/SDP2022/rs/settings/menu.yaml";
+
 impl Settings {
     pub fn new(overrides: Vec<Override>, file: std::path::PathBuf) ->  Result<Self, config::ConfigError> {
         let mut builder = Config::builder()
             .add_source(config::File::with_name("/home/robot/SDP2022/rs/settings/default.yaml"))
+            .add_source(config::File::with_name(MENU_FILE).required(false))
             .add_source(config::File::with_name(file.to_str().unwrap_or("")).required(false));
         for (key, value) in overrides {
             builder = builder.set_override(key, value)?;
@@ -0,0 +1,226 @@
+//! Split times per task, saved to a history file to compare runs with different settings.
+//!
+//! History format, one line per task: "unix time of the run;settings hash;task;follow ms;action ms"
+
+use crate::settings::Settings;
+use std::io::Write;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Split {
+    pub task: String,
+    /// following the line until the condition triggered
+    pub follow: Duration,
+    pub action: Duration,
+}
+
+impl Split {
+    pub fn total(&self) -> Duration {
+        self.follow + self.action
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    /// unix time in seconds, identifies the run
+    pub time: u64,
+    pub settings_hash: String,
+    pub splits: Vec<Split>,
+}
+
+impl Run {
+    pub fn new(settings: &Settings, splits: Vec<Split>) -> Self {
+        Self {
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            settings_hash: settings_hash(settings),
+            splits,
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.splits.iter().map(Split::total).sum()
+    }
+
+    /// same tasks in the same order
+    pub fn comparable(&self, other: &Run) -> bool {
+        self.splits.len() == other.splits.len()
+            && self
+                .splits
+                .iter()
+                .zip(&other.splits)
+                .all(|(a, b)| a.task == b.task)
+    }
+}
+
+/// FNV-1a of the settings, stable between builds unlike `DefaultHasher`
+pub fn settings_hash(settings: &Settings) -> String {
+    let hash = format!("{:?}", settings)
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
+
+pub fn load(path: &std::path::Path) -> std::io::Result<Vec<Run>> {
+    let mut runs: Vec<Run> = Vec::new();
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(runs),
+        Err(e) => return Err(e),
+    };
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(';').collect();
+        if fields.len() != 5 {
+            continue;
+        }
+        let (time, follow, action) = match (
+            fields[0].parse(),
+            fields[3].parse(),
+            fields[4].parse(),
+        ) {
+            (Ok(time), Ok(follow), Ok(action)) => (time, follow, action),
+            _ => continue,
+        };
+        let split = Split {
+            task: fields[2].to_string(),
+            follow: Duration::from_millis(follow),
+            action: Duration::from_millis(action),
+        };
+        match runs.last_mut() {
+            Some(run) if run.time == time && run.settings_hash == fields[1] => {
+                run.splits.push(split)
+            }
+            _ => runs.push(Run {
+                time,
+                settings_hash: fields[1].to_string(),
+                splits: vec![split],
+            }),
+        }
+    }
+    Ok(runs)
+}
+
+pub fn save(path: &std::path::Path, run: &Run) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    for split in &run.splits {
+        writeln!(
+            file,
+            "{};{};{};{};{}",
+            run.time,
+            run.settings_hash,
+            split.task,
+            split.follow.as_millis(),
+            split.action.as_millis()
+        )?;
+    }
+    Ok(())
+}
+
+/// the fastest earlier run with the same tasks
+pub fn best<'a>(history: &'a [Run], run: &Run) -> Option<&'a Run> {
+    history
+        .iter()
+        .filter(|other| other.comparable(run))
+        .min_by_key(|other| other.total())
+}
+
+fn delta(current: Duration, best: Duration) -> String {
+    format!("{:+.2}", current.as_secs_f32() - best.as_secs_f32())
+}
+
+pub fn print(run: &Run, best: Option<&Run>) {
+    match best {
+        Some(best) => println!(
+            "{:<16}{:>8}{:>8}{:>8}{:>8}  (best run: settings {})",
+            "split", "follow", "action", "total", "delta", best.settings_hash
+        ),
+        None => println!("{:<16}{:>8}{:>8}{:>8}", "split", "follow", "action", "total"),
+    }
+    for (index, split) in run.splits.iter().enumerate() {
+        print!(
+            "{:<16}{:>8.2}{:>8.2}{:>8.2}",
+            split.task,
+            split.follow.as_secs_f32(),
+            split.action.as_secs_f32(),
+            split.total().as_secs_f32()
+        );
+        match best {
+            Some(best) => println!("{:>8}", delta(split.total(), best.splits[index].total())),
+            None => println!(),
+        }
+    }
+    print!("{:<32}{:>8.2}", "total", run.total().as_secs_f32());
+    match best {
+        Some(best) => println!("{:>8}", delta(run.total(), best.total())),
+        None => println!(),
+    }
+    println!("settings {}", run.settings_hash);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(task: &str, follow: u64, action: u64) -> Split {
+        Split {
+            task: task.to_string(),
+            follow: Duration::from_millis(follow),
+            action: Duration::from_millis(action),
+        }
+    }
+
+    fn run(time: u64, settings_hash: &str, splits: Vec<Split>) -> Run {
+        Run {
+            time,
+            settings_hash: settings_hash.to_string(),
+            splits,
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("splits-test-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(load(&path).unwrap(), []);
+
+        let runs = [
+            run(1, "a", vec![split("turn", 1200, 300), split("stop", 50, 0)]),
+            run(2, "a", vec![split("turn", 1100, 250)]),
+            run(2, "b", vec![split("turn", 900, 400)]),
+        ];
+        for run in &runs {
+            save(&path, run).unwrap();
+        }
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "3;a;turn;not a number;0").unwrap();
+        writeln!(file, "an old line").unwrap();
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), runs);
+    }
+
+    fn settings(overrides: &[(&str, &str)]) -> Settings {
+        let mut builder = config::Config::builder().add_source(config::File::from_str(
+            include_str!("../settings/default.yaml"),
+            config::FileFormat::Yaml,
+        ));
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        builder.build().unwrap().try_deserialize().unwrap()
+    }
+
+    #[test]
+    fn hash_follows_settings() {
+        let hash = settings_hash(&settings(&[]));
+        assert_eq!(hash.len(), 16);
+        assert_eq!(hash, settings_hash(&settings(&[])));
+        assert_ne!(hash, settings_hash(&settings(&[("steering.speed", "11")])));
+    }
+}